@@ -0,0 +1,4 @@
+ALTER TABLE
+  repos
+ADD
+  COLUMN pushed_at TEXT;
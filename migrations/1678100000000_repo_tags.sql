@@ -0,0 +1,6 @@
+CREATE TABLE
+  IF NOT EXISTS repo_tags (
+    name TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (name, tag)
+  );
@@ -0,0 +1,6 @@
+CREATE TABLE
+  IF NOT EXISTS repo_topics (
+    name TEXT NOT NULL,
+    topic TEXT NOT NULL,
+    PRIMARY KEY (name, topic)
+  );
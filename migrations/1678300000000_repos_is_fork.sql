@@ -0,0 +1,4 @@
+ALTER TABLE
+  repos
+ADD
+  COLUMN is_fork BOOLEAN NOT NULL DEFAULT FALSE;
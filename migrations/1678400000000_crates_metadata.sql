@@ -0,0 +1,28 @@
+ALTER TABLE
+  crates
+ADD
+  COLUMN description TEXT;
+ALTER TABLE
+  crates
+ADD
+  COLUMN downloads INTEGER;
+ALTER TABLE
+  crates
+ADD
+  COLUMN max_version TEXT;
+ALTER TABLE
+  crates
+ADD
+  COLUMN max_stable_version TEXT;
+ALTER TABLE
+  crates
+ADD
+  COLUMN newest_version TEXT;
+ALTER TABLE
+  crates
+ADD
+  COLUMN repository TEXT;
+ALTER TABLE
+  crates
+ADD
+  COLUMN documentation TEXT;
@@ -0,0 +1,5 @@
+CREATE TABLE
+  IF NOT EXISTS selections (
+    uid TEXT PRIMARY KEY NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0
+  );
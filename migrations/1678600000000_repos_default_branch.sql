@@ -0,0 +1,4 @@
+ALTER TABLE
+  repos
+ADD
+  COLUMN default_branch TEXT;
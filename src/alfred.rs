@@ -1,32 +1,906 @@
 //! Data representation for Alfred result items
 // See [specifications](https://www.alfredapp.com/help/workflows/inputs/script-filter/json/)
 use serde::Serialize;
+use std::{cmp::Ordering, collections::BTreeMap, path::Path};
 
-use crate::{crate_client::CrateSearchItem, gh_client::GHApiRepoSearchItem};
+use crate::{
+    config::GhField,
+    crate_client::{self, CrateDependency, CrateSearchItem, CrateVersion},
+    gh_client::{GHApiRepoSearchItem, GHApiTopic},
+};
+
+/// returns true when the command is run by an Alfred workflow
+pub fn is_alfred() -> bool {
+    std::env::var_os("alfred_version").is_some()
+}
+
+/// the script filter response sent to Alfred
+#[derive(Serialize, Default)]
+pub struct AlfredResponse {
+    /// The result items
+    pub items: Vec<AlfredItem>,
+    /// Delay in seconds after which Alfred reruns the script with the same query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerun: Option<f32>,
+    /// The workflow variables shared by all the items
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+}
+
+impl AlfredResponse {
+    /// a response displaying the given items
+    /// The uids are also exposed as a variable, for the actions recording the selections
+    pub fn new(mut items: Vec<AlfredItem>) -> Self {
+        for item in &mut items {
+            if let Some(uid) = &item.uid {
+                item.variables.insert("uid".into(), uid.clone());
+            }
+        }
+        Self {
+            items,
+            rerun: None,
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// a placeholder response, rerun until the results of a slow search are available
+    pub fn loading() -> Self {
+        Self {
+            items: vec![AlfredItem::info("Searching…")],
+            rerun: Some(0.2),
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// returns the response with the given workflow variable set for all the items
+    pub fn with_variable(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.variables.insert(name.into(), value.into());
+        self
+    }
+
+    /// returns the response with word-based labels instead of emoji and symbols
+    pub fn ascii_only(self) -> Self {
+        Self {
+            items: self.items.into_iter().map(AlfredItem::ascii_only).collect(),
+            ..self
+        }
+    }
+
+    /// serialize the repositories and crates of the response to CSV rows of their name, url,
+    /// stars or downloads, and description, after a header row
+    /// The other items, e.g the info or suggestion items, are skipped
+    pub fn to_csv(&self) -> String {
+        let mut rows = vec!["name,url,stars/downloads,description".to_string()];
+        for item in &self.items {
+            let (Some(name), Some(url)) = (&item.sort_fields.name, &item.csv_fields.url) else {
+                continue;
+            };
+            let popularity = item.sort_fields.stars.or(item.sort_fields.downloads);
+            let row = [
+                name.clone(),
+                url.clone(),
+                popularity
+                    .map(|count| count.to_string())
+                    .unwrap_or_default(),
+                item.csv_fields.description.clone().unwrap_or_default(),
+            ];
+            let row = row
+                .iter()
+                .map(|field| csv_escape(field))
+                .collect::<Vec<_>>();
+            rows.push(row.join(","));
+        }
+        rows.join("\n")
+    }
+
+    /// serialize the response to the legacy XML format of the script filters
+    /// The format has no equivalent for `rerun` and the `variables`, they are skipped
+    // See [specifications](https://www.alfredapp.com/help/workflows/inputs/script-filter/xml/)
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<items>\n");
+        for item in &self.items {
+            item.write_xml(&mut xml);
+        }
+        xml.push_str("</items>");
+        xml
+    }
+}
+
+/// symbols of the generated labels, and the words replacing them
+const SYMBOL_WORDS: [(&str, &str); 8] = [
+    ("⭐ ", "favorite: "),
+    ("★ ", "stars: "),
+    (" · ", ", "),
+    ("…", "..."),
+    ("⌘", "cmd "),
+    ("⌥", "alt "),
+    ("⌃", "ctrl "),
+    ("⇧", "shift "),
+];
+
+/// symbols of the modifier keys, displayed as hints in the mod subtitles
+const MODIFIER_SYMBOLS: [(&str, &str); 4] =
+    [("cmd", "⌘"), ("alt", "⌥"), ("ctrl", "⌃"), ("shift", "⇧")];
+
+/// prefix the subtitles of the mods with their modifier keys, e.g "⌘⌥ Copy cargo add serde"
+fn with_key_hints(mods: BTreeMap<&'static str, AlfredMod>) -> BTreeMap<&'static str, AlfredMod> {
+    mods.into_iter()
+        .map(|(key, value)| {
+            let hint = key
+                .split('+')
+                .map(|modifier| {
+                    MODIFIER_SYMBOLS
+                        .iter()
+                        .find(|(name, _)| *name == modifier)
+                        .map_or(modifier, |(_, symbol)| symbol)
+                })
+                .collect::<String>();
+            let subtitle = value.subtitle.map(|subtitle| format!("{hint} {subtitle}"));
+            (key, AlfredMod { subtitle, ..value })
+        })
+        .collect()
+}
+
+/// replace the known symbols with words, and strip the other emoji and symbols
+fn ascii_label(label: &str) -> String {
+    let label = SYMBOL_WORDS
+        .iter()
+        .fold(label.to_string(), |label, (symbol, word)| {
+            label.replace(symbol, word)
+        });
+
+    let label = label
+        .chars()
+        .filter(|c| c.is_ascii() || c.is_alphanumeric() || c.is_whitespace())
+        .collect::<String>();
+
+    // collapse the spaces left around the replaced or stripped symbols
+    label.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// quote the CSV field when it contains a separator, a quote or a line break, doubling its quotes,
+/// as specified by RFC 4180
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// escape the XML special characters of the given text
+fn xml_escape(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                c => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+/// maximum number of feature flags listed in the subtitle of a crate
+const SUBTITLE_FEATURES_LIMIT: usize = 5;
+
+/// The source of an item, namespacing its uid
+/// Alfred learns the usage of the items by uid, so the items of different sources never share one
+#[derive(Debug, Clone, Copy)]
+pub enum ItemSource {
+    Github,
+    Crate,
+}
+
+impl ItemSource {
+    /// the prefix of the uids of this source, none of them is a prefix of another one
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::Github => "gh:",
+            Self::Crate => "crate:",
+        }
+    }
+
+    /// returns the uid of the item of this source with the given name, e.g `gh:owner/name`
+    pub fn uid(self, name: &str) -> String {
+        format!("{}{name}", self.prefix())
+    }
+}
 
 /// a result item for Alfred
 #[derive(Serialize, Default)]
 pub struct AlfredItem {
+    /// The identifier Alfred learns the usage of the item with, see `ItemSource::uid`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
     /// The title displayed in the result row
     pub title: String,
+    /// The subtitle displayed in the result row
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    /// The argument passed to the connected output action
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arg: Option<String>,
+    /// Whether the item can be actioned, defaults to true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid: Option<bool>,
+    /// The query completed with tab, or when actioning an invalid item
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autocomplete: Option<String>,
+    /// The url or file path previewed with Quick Look (⇧ or ⌘Y)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quicklookurl: Option<String>,
+    /// The text copied (⌘C) or displayed in large type (⌘L)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<AlfredText>,
+    /// The alternative actions triggered when holding modifier keys, keyed by modifier (e.g `alt`)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub mods: BTreeMap<&'static str, AlfredMod>,
+    /// The workflow variables set when the item is actioned
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    /// The fields the results can be sorted on, not sent to Alfred
+    #[serde(skip)]
+    pub sort_fields: SortFields,
+    /// The fields exported by the CSV format, not sent to Alfred
+    #[serde(skip)]
+    pub csv_fields: CsvFields,
+}
+
+/// The fields of an item exported by the CSV format, besides its sort fields
+#[derive(Default)]
+pub struct CsvFields {
+    /// the web page of the repository or crate, whatever the arg of the item
+    pub url: Option<String>,
+    pub description: Option<String>,
+}
+
+/// The fields of an item the results can be sorted on with `--sort`
+#[derive(Default)]
+pub struct SortFields {
+    /// the name of the repository or crate, without the decorations of the title
+    pub name: Option<String>,
+    pub stars: Option<u64>,
+    pub downloads: Option<u64>,
+    /// the last push to the repository, or the last update of the crate
+    pub updated: Option<chrono::DateTime<chrono::FixedOffset>>,
+}
+
+/// A field of the items to sort the results on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Name,
+    Stars,
+    Downloads,
+    Updated,
+}
+
+/// The order of the results requested with `--sort`
+#[derive(Debug, Clone, Copy)]
+pub struct ItemSort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl ItemSort {
+    /// sort the items on the field, the items without this field come last
+    pub fn apply(&self, items: &mut [AlfredItem]) {
+        items.sort_by(|a, b| {
+            let (a, b) = (&a.sort_fields, &b.sort_fields);
+            match self.field {
+                SortField::Name => self.compare(
+                    a.name.as_ref().map(|name| name.to_lowercase()),
+                    b.name.as_ref().map(|name| name.to_lowercase()),
+                ),
+                SortField::Stars => self.compare(a.stars, b.stars),
+                SortField::Downloads => self.compare(a.downloads, b.downloads),
+                SortField::Updated => self.compare(a.updated, b.updated),
+            }
+        });
+    }
+
+    /// compare the known values in the requested direction, missing values last
+    fn compare<T: Ord>(&self, a: Option<T>, b: Option<T>) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) if self.descending => b.cmp(&a),
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+}
+
+/// parse an RFC 3339 timestamp of the APIs
+fn parse_timestamp(value: Option<&str>) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    value.and_then(|value| chrono::DateTime::parse_from_rfc3339(value).ok())
+}
+
+/// The text of an item copied or displayed in large type
+#[derive(Serialize, Default)]
+pub struct AlfredText {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub largetype: Option<String>,
+}
+
+/// An alternative action of an item, triggered when holding a modifier key
+#[derive(Serialize, Default)]
+pub struct AlfredMod {
+    /// The argument passed to the connected output action
+    pub arg: String,
+    /// The subtitle displayed while the modifier is held
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    /// The workflow variables set when the modifier action is triggered
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+}
+
+impl AlfredMod {
+    /// a modifier action copying the given snippet
+    fn copy(snippet: String) -> Self {
+        Self {
+            subtitle: Some(format!("Copy {snippet}")),
+            arg: snippet,
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// a modifier action opening the given link
+    fn open(label: &str, url: &str) -> Self {
+        Self {
+            subtitle: Some(format!("Open {label} {url}")),
+            arg: url.to_string(),
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// a modifier action opening the given local clone, flagged with the `local` variable so
+    /// that the workflow opens it in the editor
+    fn open_local(path: &Path) -> Self {
+        let path = path.to_string_lossy();
+        Self {
+            subtitle: Some(format!("Open local clone {path}")),
+            arg: path.into_owned(),
+            variables: BTreeMap::from([("local".to_string(), "true".to_string())]),
+        }
+    }
+}
+
+impl AlfredItem {
+    /// a non actionable item, displaying an information to the user
+    /// It has no uid, so that Alfred never learns it and reorders the results around it
+    pub fn info(title: impl Into<String>) -> Self {
+        Self {
+            uid: None,
+            title: title.into(),
+            valid: Some(false),
+            ..Default::default()
+        }
+    }
+
+    /// returns the item with word-based labels instead of emoji and symbols
+    fn ascii_only(self) -> Self {
+        Self {
+            title: ascii_label(&self.title),
+            subtitle: self.subtitle.as_deref().map(ascii_label),
+            mods: self
+                .mods
+                .into_iter()
+                .map(|(key, value)| {
+                    let subtitle = value.subtitle.as_deref().map(ascii_label);
+                    (key, AlfredMod { subtitle, ..value })
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    /// append the legacy XML `<item>` element of the item
+    fn write_xml(&self, xml: &mut String) {
+        let valid = if self.valid.unwrap_or(true) {
+            "yes"
+        } else {
+            "no"
+        };
+        // the uid is kept stable when the title is decorated, e.g for the favorites, and the non
+        // actionable items have none, so that Alfred never learns them
+        xml.push_str("  <item");
+        if self.valid.unwrap_or(true) {
+            let uid = self
+                .uid
+                .as_ref()
+                .or(self.arg.as_ref())
+                .unwrap_or(&self.title);
+            xml.push_str(&format!(" uid=\"{}\"", xml_escape(uid)));
+        }
+        xml.push_str(&format!(" valid=\"{valid}\""));
+        if let Some(arg) = &self.arg {
+            xml.push_str(&format!(" arg=\"{}\"", xml_escape(arg)));
+        }
+        if let Some(autocomplete) = &self.autocomplete {
+            xml.push_str(&format!(" autocomplete=\"{}\"", xml_escape(autocomplete)));
+        }
+        xml.push_str(">\n");
+
+        let mut element = |name: &str, attributes: &str, value: &str| {
+            xml.push_str(&format!(
+                "    <{name}{attributes}>{}</{name}>\n",
+                xml_escape(value)
+            ));
+        };
+        element("title", "", &self.title);
+        if let Some(subtitle) = &self.subtitle {
+            element("subtitle", "", subtitle);
+        }
+        if let Some(quicklookurl) = &self.quicklookurl {
+            element("quicklookurl", "", quicklookurl);
+        }
+        if let Some(text) = &self.text {
+            if let Some(copy) = &text.copy {
+                element("text", " type=\"copy\"", copy);
+            }
+            if let Some(largetype) = &text.largetype {
+                element("text", " type=\"largetype\"", largetype);
+            }
+        }
+
+        for (key, value) in &self.mods {
+            xml.push_str(&format!(
+                "    <mod key=\"{key}\" arg=\"{}\"",
+                xml_escape(&value.arg)
+            ));
+            if let Some(subtitle) = &value.subtitle {
+                xml.push_str(&format!(" subtitle=\"{}\"", xml_escape(subtitle)));
+            }
+            xml.push_str("/>\n");
+        }
+        xml.push_str("  </item>\n");
+    }
+
+    /// returns the item marked as a pinned favorite
+    pub fn favorite(self) -> Self {
+        Self {
+            title: format!("⭐ {}", self.title),
+            ..self
+        }
+    }
+
+    /// an item of a Github topic, completed to the search of its repositories when actioned
+    pub fn topic(topic: GHApiTopic) -> Self {
+        let curated = (topic.featured || topic.curated).then(|| "★ curated".to_string());
+        Self {
+            title: topic.display_name.unwrap_or_else(|| topic.name.clone()),
+            subtitle: subtitle([
+                Some(format!("topic:{}", topic.name)),
+                curated,
+                topic.short_description,
+            ]),
+            valid: Some(false),
+            autocomplete: Some(format!("topic:{} ", topic.name)),
+            ..Default::default()
+        }
+    }
+
+    /// an item suggesting a crate name close to the query, completed when actioned
+    pub fn suggestion(name: &str) -> Self {
+        Self {
+            title: format!("Did you mean {name}?"),
+            valid: Some(false),
+            autocomplete: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// an item opening the docs.rs page of the given version of a crate
+    pub fn crate_version(name: &str, version: &CrateVersion) -> Self {
+        let mut mods = BTreeMap::new();
+        mods.insert(
+            "alt",
+            AlfredMod::copy(format!("{name} = \"{}\"", version.num)),
+        );
+
+        Self {
+            uid: Some(ItemSource::Crate.uid(&format!("{name}@{}", version.num))),
+            title: format!("{name} v{}", version.num),
+            subtitle: subtitle([
+                version.yanked.then(|| "yanked".to_string()),
+                version
+                    .created_at
+                    .as_deref()
+                    .and_then(|created_at| created_at.get(..10))
+                    .map(|date| format!("published {date}")),
+            ]),
+            arg: Some(format!("https://docs.rs/{name}/{}", version.num)),
+            mods: with_key_hints(mods),
+            sort_fields: SortFields {
+                name: Some(name.to_string()),
+                updated: parse_timestamp(version.created_at.as_deref()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// an item opening the crates.io page of the given crate
+    pub fn open_crate(name: &str) -> Self {
+        Self {
+            uid: Some(ItemSource::Crate.uid(name)),
+            title: format!("Open {name} on crates.io"),
+            arg: Some(crate_client::crate_url(name)),
+            sort_fields: SortFields {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
 }
 
 impl From<String> for AlfredItem {
     fn from(val: String) -> Self {
-        Self { title: val }
+        Self {
+            title: val,
+            ..Default::default()
+        }
+    }
+}
+
+/// join the available details into a subtitle
+fn subtitle(details: impl IntoIterator<Item = Option<String>>) -> Option<String> {
+    let details = details.into_iter().flatten().collect::<Vec<_>>();
+    (!details.is_empty()).then(|| details.join(" · "))
+}
+
+/// The value carried by the `arg` of the repository items, see `--repo-format`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum RepoFormat {
+    /// the https url of the repository page, e.g `https://github.com/owner/name`
+    #[default]
+    Https,
+    /// the ssh clone url, e.g `git@github.com:owner/name.git`
+    Ssh,
+    /// the full name, e.g `owner/name`
+    #[clap(name = "full_name")]
+    FullName,
+    /// the name without its owner, e.g `name`
+    Slug,
+}
+
+impl RepoFormat {
+    /// returns the arg of the repository with the given full name
+    pub fn arg(self, full_name: &str) -> String {
+        match self {
+            Self::Https => format!("https://github.com/{full_name}"),
+            Self::Ssh => format!("git@github.com:{full_name}.git"),
+            Self::FullName => full_name.to_string(),
+            Self::Slug => full_name
+                .split_once('/')
+                .map_or(full_name, |(_, name)| name)
+                .to_string(),
+        }
     }
 }
 
 impl From<GHApiRepoSearchItem> for AlfredItem {
     fn from(val: GHApiRepoSearchItem) -> Self {
+        Self::repository(val, RepoFormat::default(), &GhField::ALL)
+    }
+}
+
+impl AlfredItem {
+    /// an item of the given repository, whose arg has the given format, and whose subtitle only
+    /// shows the given optional details
+    /// The hidden details are still sortable, e.g the stars of `--sort stars`
+    pub fn repository(val: GHApiRepoSearchItem, format: RepoFormat, fields: &[GhField]) -> Self {
+        let shown = |field| fields.contains(&field);
+        let sort_fields = SortFields {
+            name: Some(val.full_name.clone()),
+            stars: val.stargazers_count,
+            downloads: None,
+            updated: parse_timestamp(val.pushed_at.as_deref()),
+        };
+
+        // e.g for the clone actions of the workflow
+        let mut variables = BTreeMap::new();
+        if let Some(branch) = &val.default_branch {
+            variables.insert("default_branch".into(), branch.clone());
+        }
+
+        let mut mods = BTreeMap::new();
+        if let Some(path) = &val.local_path {
+            mods.insert("cmd", AlfredMod::open_local(path));
+        }
+
+        let csv_fields = CsvFields {
+            url: Some(RepoFormat::Https.arg(&val.full_name)),
+            description: val.description.clone(),
+        };
+
         Self {
+            uid: Some(ItemSource::Github.uid(&val.full_name)),
+            sort_fields,
+            csv_fields,
+            subtitle: subtitle([
+                val.stargazers_count
+                    .filter(|_| shown(GhField::Stars))
+                    .map(|stars| format!("★ {stars}")),
+                val.languages
+                    .filter(|languages| shown(GhField::Languages) && !languages.is_empty())
+                    .map(|languages| {
+                        languages
+                            .iter()
+                            .map(|language| format!("{} {}%", language.name, language.percent))
+                            .collect::<Vec<_>>()
+                            .join(" · ")
+                    }),
+                val.matched_topic.map(|topic| format!("topic: {topic}")),
+                val.good_first_issues
+                    .map(|count| format!("{count} good first issues")),
+                val.description.filter(|_| shown(GhField::Description)),
+            ]),
+            arg: Some(format.arg(&val.full_name)),
+            quicklookurl: val
+                .readme_path
+                .map(|path| path.to_string_lossy().into_owned()),
             title: val.full_name,
+            mods: with_key_hints(mods),
+            variables,
+            ..Default::default()
         }
     }
 }
 
 impl From<CrateSearchItem> for AlfredItem {
     fn from(value: CrateSearchItem) -> Self {
-        Self { title: value.name }
+        // snippets to add the crate as a dependency
+        let mut mods = BTreeMap::new();
+        if let Some(version) = value.recommended_version() {
+            mods.insert(
+                "alt",
+                AlfredMod::copy(format!("{} = \"{version}\"", value.name)),
+            );
+        }
+        mods.insert(
+            "cmd+alt",
+            AlfredMod::copy(format!("cargo add {}", value.name)),
+        );
+
+        // links of the crate, when known
+        if let Some(repository) = &value.repository {
+            mods.insert("cmd", AlfredMod::open("repository", repository));
+        }
+        let documentation = value
+            .documentation
+            .clone()
+            .unwrap_or_else(|| format!("https://docs.rs/{}", value.name));
+        mods.insert("shift", AlfredMod::open("documentation", &documentation));
+        if let Some(owner) = value.owners.as_ref().and_then(|owners| owners.first()) {
+            mods.insert("ctrl", AlfredMod::open(&owner.login, &owner.profile_url()));
+        }
+
+        // a yanked newest version is not worth depending on, show the stable one instead
+        let version = match (value.newest_version_yanked, &value.max_stable_version) {
+            (Some(true), Some(stable)) => Some(format!("latest yanked, stable v{stable}")),
+            _ => value
+                .max_version
+                .as_ref()
+                .map(|version| format!("v{version}")),
+        };
+
+        // extra fields of the registry come first, so they can't shadow the built-in variables
+        let mut variables = value.extra_variables.clone();
+        if let Some(series) = &value.downloads_series {
+            let series = series.iter().map(u64::to_string).collect::<Vec<_>>();
+            variables.insert("downloads_series".into(), series.join(","));
+        }
+        if let Some(rust_version) = &value.rust_version {
+            variables.insert("rust_version".into(), rust_version.clone());
+        }
+        if let Some(edition) = &value.edition {
+            variables.insert("edition".into(), edition.clone());
+        }
+        if let Some(owners) = value.owners.as_ref().filter(|owners| !owners.is_empty()) {
+            let logins = owners.iter().map(|owner| owner.login.as_str());
+            variables.insert("owners".into(), logins.collect::<Vec<_>>().join(","));
+        }
+
+        // the subtitle only lists the first features, the large type text lists them all
+        let mut largetype = None;
+        let mut features = None;
+        if let Some(names) = value.features.as_ref().filter(|names| !names.is_empty()) {
+            variables.insert("features".into(), names.join(","));
+            largetype = Some(format!("{}\nfeatures: {}", value.name, names.join(", ")));
+
+            let mut listed = names
+                .iter()
+                .take(SUBTITLE_FEATURES_LIMIT)
+                .cloned()
+                .collect::<Vec<_>>();
+            if names.len() > SUBTITLE_FEATURES_LIMIT {
+                listed.push(format!("+{}", names.len() - SUBTITLE_FEATURES_LIMIT));
+            }
+            features = Some(format!("features: {}", listed.join(", ")));
+        }
+
+        let sort_fields = SortFields {
+            name: Some(value.name.clone()),
+            stars: None,
+            downloads: value.downloads,
+            updated: parse_timestamp(value.updated_at.as_deref()),
+        };
+
+        let csv_fields = CsvFields {
+            url: Some(crate_client::crate_url(&value.name)),
+            description: value.description.clone(),
+        };
+
+        Self {
+            uid: Some(ItemSource::Crate.uid(&value.name)),
+            sort_fields,
+            csv_fields,
+            text: Some(AlfredText {
+                copy: Some(value.name.clone()),
+                largetype,
+            }),
+            mods: with_key_hints(mods),
+            variables,
+            subtitle: subtitle([
+                version,
+                value
+                    .downloads
+                    .map(|downloads| format!("{downloads} downloads")),
+                value
+                    .rust_version
+                    .map(|rust_version| format!("MSRV {rust_version}")),
+                value.edition.map(|edition| format!("edition {edition}")),
+                // a match of the description shows the matched part instead of its start
+                value
+                    .matched_description
+                    .map(|snippet| format!("description: {snippet}"))
+                    .or(value.description),
+                features,
+            ]),
+            arg: Some(crate_client::crate_url(&value.name)),
+            title: value.name,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<CrateDependency> for AlfredItem {
+    fn from(value: CrateDependency) -> Self {
+        Self {
+            subtitle: subtitle([
+                Some(value.kind.label().to_string()),
+                Some(value.req),
+                value.optional.then(|| "optional".to_string()),
+            ]),
+            uid: Some(ItemSource::Crate.uid(&value.crate_id)),
+            arg: Some(crate_client::crate_url(&value.crate_id)),
+            sort_fields: SortFields {
+                name: Some(value.crate_id.clone()),
+                ..Default::default()
+            },
+            csv_fields: CsvFields {
+                url: Some(crate_client::crate_url(&value.crate_id)),
+                description: None,
+            },
+            title: value.crate_id,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(num: &str) -> CrateVersion {
+        serde_json::from_value(serde_json::json!({ "num": num, "yanked": false })).unwrap()
+    }
+
+    #[test]
+    fn csv_fields_are_only_quoted_when_needed() {
+        assert_eq!(csv_escape("an async runtime"), "an async runtime");
+        assert_eq!(csv_escape("fast, safe"), r#""fast, safe""#);
+        assert_eq!(
+            csv_escape(r#"the "fast" runtime"#),
+            r#""the ""fast"" runtime""#
+        );
+        assert_eq!(csv_escape("first\nsecond"), "\"first\nsecond\"");
+        assert_eq!(csv_escape("first\r\nsecond"), "\"first\r\nsecond\"");
+    }
+
+    #[test]
+    fn csv_rows_quote_the_descriptions() {
+        let item = CrateSearchItem {
+            name: "tokio".to_string(),
+            description: Some("An \"async\" runtime,\nfor Rust".to_string()),
+            downloads: Some(42),
+            ..Default::default()
+        };
+        let response = AlfredResponse {
+            items: vec![AlfredItem::info("Cache is 3 days old"), item.into()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response.to_csv(),
+            "name,url,stars/downloads,description\n\
+             tokio,https://crates.io/crates/tokio,42,\"An \"\"async\"\" runtime,\nfor Rust\""
+        );
+    }
+
+    #[test]
+    fn info_items_have_no_uid_in_json() {
+        let json = serde_json::to_value(AlfredItem::info("Cache is 3 days old")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "title": "Cache is 3 days old", "valid": false })
+        );
+    }
+
+    #[test]
+    fn info_items_have_no_uid_in_xml() {
+        let response = AlfredResponse {
+            items: vec![
+                AlfredItem::info("Cache is 3 days old"),
+                AlfredItem::open_crate("tokio"),
+            ],
+            ..Default::default()
+        };
+        let xml = response.to_xml();
+
+        assert!(xml.contains(r#"<item valid="no">"#), "{xml}");
+        assert!(
+            xml.contains(r#"<item uid="crate:tokio" valid="yes""#),
+            "{xml}"
+        );
+    }
+
+    #[test]
+    fn uids_of_the_same_name_differ_between_sources() {
+        let repo = AlfredItem::from(GHApiRepoSearchItem {
+            full_name: "tokio".to_string(),
+            ..Default::default()
+        });
+        let krate = AlfredItem::from(CrateSearchItem {
+            name: "tokio".to_string(),
+            ..Default::default()
+        });
+
+        assert_ne!(repo.uid, krate.uid);
+        assert!(!ItemSource::Github
+            .prefix()
+            .starts_with(ItemSource::Crate.prefix()));
+        assert!(!ItemSource::Crate
+            .prefix()
+            .starts_with(ItemSource::Github.prefix()));
+    }
+
+    #[test]
+    fn uids_identify_the_crate_and_each_of_its_versions() {
+        let krate = AlfredItem::from(CrateSearchItem {
+            name: "tokio".to_string(),
+            ..Default::default()
+        });
+        let uids = [
+            krate.uid.clone(),
+            AlfredItem::crate_version("tokio", &version("1.0.0")).uid,
+            AlfredItem::crate_version("tokio", &version("1.0.1")).uid,
+        ];
+
+        assert_eq!(AlfredItem::open_crate("tokio").uid, krate.uid);
+        assert!(uids.iter().all(Option::is_some));
+        assert_eq!(
+            uids.iter().collect::<std::collections::HashSet<_>>().len(),
+            uids.len()
+        );
     }
 }
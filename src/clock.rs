@@ -0,0 +1,28 @@
+//! Time source abstraction, so that time dependent logic can run against a frozen clock
+use chrono::{DateTime, Utc};
+
+/// A source of the current time
+pub trait Clock {
+    /// returns the current time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock frozen at the given time
+#[cfg(test)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
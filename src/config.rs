@@ -1,45 +1,643 @@
 //! Manage the persisted configuration of the cli
+use crate::clock::Clock;
+use crate::db_client::DBCounts;
 use confy::ConfyError;
+use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::OnceLock,
+};
 
 const DEFAULT_CONFIG_NAME: &str = env!("CARGO_PKG_NAME");
 
+/// move the corrupt config file aside, so that the next load starts from a fresh default file
+fn backup_corrupt_config() {
+    let path = match ProjectDirs::from("rs", "", DEFAULT_CONFIG_NAME) {
+        Some(dirs) => dirs
+            .config_dir()
+            .join(format!("{DEFAULT_CONFIG_NAME}.toml")),
+        None => return,
+    };
+
+    let backup_path = path.with_extension("toml.bak");
+    match std::fs::rename(&path, &backup_path) {
+        Ok(()) => log::warn!("corrupt config file moved to {}", backup_path.display()),
+        Err(err) => log::warn!("failed to back up the corrupt config file: {err}"),
+    }
+}
+
 /// stored configuration for gh_alfred
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GhAlfredConfig {
     /// The last time we spawned a daemon fork to update the cache
     pub last_update_start_time: Option<chrono::DateTime<chrono::Local>>,
+    /// The last time an update of the cache completed
+    pub last_update_success_time: Option<chrono::DateTime<chrono::Local>>,
+    /// Login of the authenticated Github user, resolved once for `--mine`
+    /// Remove it after switching to the token of another user
+    pub viewer_login: Option<String>,
+    /// Number of minutes after which the cache is considered stale, and updated in the background
+    pub refresh_after_minutes: u32,
+    /// Number of cached rows (repos and crates) from which the cache is considered healthy (0 = off)
+    pub healthy_cache_min_rows: i64,
+    /// Number of minutes after which a healthy cache is updated, instead of `refresh_after_minutes`
+    pub healthy_cache_refresh_after_minutes: u32,
+    /// Number of days without a completed update after which the searches show a warning (0 = off)
+    pub stale_warning_age_days: u32,
+    /// Number of top cached results for which we fetch extra details from the API (0 = off)
+    pub enrich_limit: usize,
+    /// How the cached and live results are combined
+    pub search_strategy: SearchStrategy,
+    /// Which items of the combined results are duplicates, e.g whether `user/serde` is dropped
+    /// when `serde-rs/serde` is found first
+    pub dedup_key: DedupKey,
+    /// Preview the README of the top repository with Quick Look, fetching it if needed
+    pub quicklook_readme: bool,
+    /// Use word-based labels instead of emoji and symbols, e.g for screen readers
+    pub ascii_only: bool,
+    /// Create the database and Github clients in the background as soon as a search starts
+    pub prewarm_clients: bool,
+    /// Skip the forked repositories in the cached and live results
+    pub hide_forks: bool,
+    /// Negotiate gzip or deflate compressed API responses, can be disabled to debug the raw responses
+    pub compress_responses: bool,
+    /// Suggest close crate names when a crate search has no results, at the cost of extra calls
+    pub crate_suggestions: bool,
+    /// Move a corrupt database aside and start over with an empty cache, instead of failing
+    pub repair_database: bool,
+    /// Maximum number of repositories of the same owner in the search results (unset or 0 =
+    /// unlimited)
+    pub max_per_owner: Option<usize>,
+    /// Application opening the urls of the results, e.g `Safari`, exposed to the workflow as the
+    /// `open_in` variable (unset = the default browser)
+    pub open_in: Option<String>,
+    /// Directory of the log files, defaults to the Alfred workflow cache directory
+    pub log_dir: Option<PathBuf>,
+    /// Database snapshot copied into place when the cache doesn't exist yet, e.g `snapshot.db`
+    /// bundled in the workflow folder, so that a fresh install has results before the first sync
+    pub seed_snapshot: Option<PathBuf>,
+    /// Size in MB after which the log file is rotated
+    pub log_rotate_size_mb: u64,
+    /// Number of repository pages fetched ahead of the database writes while syncing
+    pub sync_pages_in_flight: usize,
+    /// Maximum number of repository pages fetched by a sync, as a safety net against runaway syncs
+    pub sync_max_pages: usize,
+    /// Maximum number of concurrent requests to the Github and crates.io APIs, shared by the
+    /// searches, the enrichment and the syncs
+    pub max_concurrent_requests: usize,
+    /// Timeout in seconds of the Github API calls of the searches (0 = the default)
+    pub gh_search_timeout_secs: u64,
+    /// Timeout in seconds of each page of repositories fetched by a sync (0 = the default)
+    pub gh_sync_timeout_secs: u64,
+    /// Timeout in seconds of the crates registry API calls (0 = the default)
+    pub crate_search_timeout_secs: u64,
+    /// Optional details of the Github repositories shown in the results, among `stars`,
+    /// `description` and `languages`, the enrichment only fetching the shown ones
+    pub gh_fields: Vec<GhField>,
+    /// Directories of the local clones, a repository cloned as `<root>/<name>` or
+    /// `<root>/<owner>/<name>` gets a modifier opening the clone, e.g `~/code`
+    pub clone_roots: Vec<PathBuf>,
+    /// Urls of extra cache databases, e.g from another account, included in the searches
+    pub extra_database_urls: Vec<String>,
+    /// Items pinned first when they match the search, as `gh:owner/name` or `crate:name`
+    pub favorites: Vec<String>,
+    /// How the sync paces its calls against the Github API rate limit
+    pub rate_limit_strategy: RateLimitStrategy,
+    // tables are serialized after the plain values, as required by TOML
+    /// Order in which the sources are searched
+    pub source_priority: SourcePriority,
+    /// Weights of the signals combined to rank the search results
+    pub ranking_weights: RankingWeights,
+    /// Number of search results scaled by the length of the query
+    pub adaptive_limit: AdaptiveLimit,
+    /// Github App used to authorize the API calls, instead of GITHUB_API_TOKEN
+    pub github_app: Option<GithubAppConfig>,
+    /// Extra fields of the crate search results exposed as Alfred variables, keyed by variable
+    /// name, e.g `owner = "/meta/owner"` (JSON pointer into each search result)
+    pub crate_extra_fields: BTreeMap<String, String>,
+    /// Named setups selected with `--profile`, the `default` one is used when no profile is given
+    pub profiles: HashMap<String, ProfileConfig>,
+}
+
+/// A named setup, overriding the environment variables and limits of the config
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    /// overrides GITHUB_API_TOKEN
+    pub github_api_token: Option<String>,
+    /// overrides DATABASE_URL
+    pub database_url: Option<String>,
+    /// overrides CRATES_REGISTRY_URL
+    pub crates_registry_url: Option<String>,
+    /// overrides CRATES_REGISTRY_TOKEN
+    pub crates_registry_token: Option<String>,
+    /// overrides `enrich_limit`
+    pub enrich_limit: Option<usize>,
+}
+
+/// Name of the profile used when no profile is given
+const DEFAULT_PROFILE: &str = "default";
+
+/// The profile selected for this invocation
+static SELECTED_PROFILE: OnceLock<ProfileConfig> = OnceLock::new();
+
+/// select the given profile, or the default one, for this invocation
+/// The profile variables take precedence over the environment, and the .env file
+/// # Note
+/// This updates the environment, so it must be called before spawning any thread
+pub fn select_profile(name: Option<&str>) -> anyhow::Result<()> {
+    let mut profiles = GhAlfredConfig::load()?.profiles;
+    let profile = match name {
+        Some(name) => profiles
+            .remove(name)
+            .ok_or_else(|| anyhow::format_err!("unknown profile {name}"))?,
+        None => profiles.remove(DEFAULT_PROFILE).unwrap_or_default(),
+    };
+
+    let variables = [
+        ("GITHUB_API_TOKEN", &profile.github_api_token),
+        ("DATABASE_URL", &profile.database_url),
+        ("CRATES_REGISTRY_URL", &profile.crates_registry_url),
+        ("CRATES_REGISTRY_TOKEN", &profile.crates_registry_token),
+    ];
+    for (key, value) in variables {
+        if let Some(value) = value {
+            std::env::set_var(key, value);
+        }
+    }
+
+    SELECTED_PROFILE
+        .set(profile)
+        .map_err(|_| anyhow::format_err!("a profile is already selected"))
+}
+
+/// Credentials of a Github App, used to mint short-lived installation tokens
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GithubAppConfig {
+    pub app_id: u64,
+    /// installation to authorize as, defaults to the first installation of the app
+    #[serde(default)]
+    pub installation_id: Option<u64>,
+    /// path of the PEM private key of the app
+    pub private_key_path: PathBuf,
+}
+
+/// A source of search results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// the local database
+    Cache,
+    /// the Github or crates.io API
+    Live,
+}
+
+/// Order in which the sources are searched, per search command
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourcePriority {
+    pub repos: Vec<Source>,
+    pub crates: Vec<Source>,
+}
+
+impl Default for SourcePriority {
+    fn default() -> Self {
+        Self {
+            repos: vec![Source::Cache, Source::Live],
+            crates: vec![Source::Cache, Source::Live],
+        }
+    }
+}
+
+/// Weights of the signals combined into the ranking score of a search result
+/// The match weights apply to the best match of the query, the other signals are normalized to [0, 1]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RankingWeights {
+    /// the name equals the query
+    pub exact: f64,
+    /// the name starts with the query
+    pub prefix: f64,
+    /// the name contains the query
+    pub substring: f64,
+    /// stars of the repositories
+    pub stars: f64,
+    /// downloads of the crates
+    pub downloads: f64,
+    /// how recently the repositories were pushed
+    pub recency: f64,
+    /// how often the items were selected from the results
+    pub selections: f64,
+}
+
+impl Default for RankingWeights {
+    fn default() -> Self {
+        Self {
+            exact: 3.0,
+            prefix: 2.0,
+            substring: 1.0,
+            stars: 0.5,
+            downloads: 0.5,
+            recency: 0.0,
+            selections: 1.0,
+        }
+    }
+}
+
+/// Number of search results scaled by the length of the query, so that short and vague queries
+/// only show their best matches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveLimit {
+    /// scale the number of results, instead of always showing the maximum
+    pub enabled: bool,
+    /// number of results of the queries of at most `short_query_len` characters
+    pub min_results: usize,
+    pub short_query_len: usize,
+    /// queries of at least this number of characters show the maximum number of results, the
+    /// number grows linearly for the queries in between
+    pub long_query_len: usize,
+}
+
+impl Default for AdaptiveLimit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_results: 3,
+            short_query_len: 2,
+            long_query_len: 5,
+        }
+    }
+}
+
+impl AdaptiveLimit {
+    /// returns the number of results to show for the given query, at most `max_results`
+    pub fn limit(&self, query: &str, max_results: usize) -> usize {
+        if !self.enabled {
+            return max_results;
+        }
+
+        let min_results = self.min_results.min(max_results);
+        let len = query.trim().chars().count();
+        if len <= self.short_query_len {
+            min_results
+        } else if len >= self.long_query_len {
+            max_results
+        } else {
+            min_results
+                + (max_results - min_results) * (len - self.short_query_len)
+                    / (self.long_query_len - self.short_query_len)
+        }
+    }
+}
+
+impl RankingWeights {
+    /// returns an error if one of the weights is negative
+    fn validate(&self) -> anyhow::Result<()> {
+        let weights = [
+            ("exact", self.exact),
+            ("prefix", self.prefix),
+            ("substring", self.substring),
+            ("stars", self.stars),
+            ("downloads", self.downloads),
+            ("recency", self.recency),
+            ("selections", self.selections),
+        ];
+
+        match weights
+            .iter()
+            .find(|(_, weight)| weight.is_nan() || *weight < 0.0)
+        {
+            Some((name, weight)) => Err(anyhow::format_err!(
+                "ranking weight {name} must be non-negative, got {weight}"
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How the sync paces its calls against the Github API rate limit
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitStrategy {
+    /// call the API as fast as possible, and wait for the reset once the budget is exhausted
+    #[default]
+    Burst,
+    /// spread the remaining budget until the reset, so that it's never fully exhausted
+    Smooth,
+}
+
+/// An optional detail of the Github repositories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GhField {
+    /// the number of stars
+    Stars,
+    /// the description
+    Description,
+    /// the main languages, fetched with an extra call when enriching the items
+    Languages,
+}
+
+impl GhField {
+    /// all the optional details, shown by default
+    pub const ALL: [Self; 3] = [Self::Stars, Self::Description, Self::Languages];
+}
+
+impl Default for GhAlfredConfig {
+    fn default() -> Self {
+        Self {
+            last_update_start_time: None,
+            last_update_success_time: None,
+            viewer_login: None,
+            refresh_after_minutes: 30,
+            healthy_cache_min_rows: 0,
+            healthy_cache_refresh_after_minutes: 240,
+            stale_warning_age_days: 3,
+            enrich_limit: 0,
+            search_strategy: SearchStrategy::default(),
+            dedup_key: DedupKey::default(),
+            quicklook_readme: false,
+            ascii_only: false,
+            prewarm_clients: true,
+            hide_forks: false,
+            compress_responses: true,
+            crate_suggestions: false,
+            repair_database: true,
+            max_per_owner: None,
+            open_in: None,
+            source_priority: SourcePriority::default(),
+            ranking_weights: RankingWeights::default(),
+            adaptive_limit: AdaptiveLimit::default(),
+            log_dir: None,
+            seed_snapshot: None,
+            log_rotate_size_mb: 10,
+            sync_pages_in_flight: 2,
+            sync_max_pages: 1000,
+            max_concurrent_requests: 8,
+            gh_search_timeout_secs: 10,
+            gh_sync_timeout_secs: 60,
+            crate_search_timeout_secs: 10,
+            gh_fields: GhField::ALL.to_vec(),
+            clone_roots: Vec::new(),
+            extra_database_urls: Vec::new(),
+            favorites: Vec::new(),
+            rate_limit_strategy: RateLimitStrategy::default(),
+            github_app: None,
+            crate_extra_fields: BTreeMap::new(),
+            profiles: HashMap::new(),
+        }
+    }
+}
+
+/// How the cached and live results are combined
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchStrategy {
+    /// search the sources in priority order, and return the results of the first one with results
+    #[default]
+    Fallback,
+    /// search all the sources, and rank the merged results
+    Combined,
+}
+
+/// The key of the combined results dropped as duplicates
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupKey {
+    /// the repository name without its owner, or the crate name
+    #[default]
+    Name,
+    /// the repository name with its owner, keeping the same named repositories of other owners
+    FullName,
+    /// keep all the results, even the items found by several sources
+    None,
+}
+
+/// Why the cache is stale, see `GhAlfredConfig::stale_cache`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleCache {
+    /// no update ever completed, e.g the first sync failed
+    NeverSynced,
+    /// the number of days since the last completed update
+    DaysOld(i64),
 }
 
 impl GhAlfredConfig {
     /// load the config from the default disk location
+    /// A corrupt config file (e.g partially written) is backed up and replaced by the defaults,
+    /// instead of failing every command, and so is a missing config file that can't be created
     pub fn load() -> Result<GhAlfredConfig, ConfyError> {
-        confy::load::<GhAlfredConfig>(DEFAULT_CONFIG_NAME)
+        match confy::load::<GhAlfredConfig>(DEFAULT_CONFIG_NAME) {
+            Err(ConfyError::BadTomlData(err)) => {
+                log::warn!("corrupt config file, falling back to the default config: {err}");
+                backup_corrupt_config();
+                Ok(Default::default())
+            }
+            // the default config can't be written on first load, e.g in a read-only config directory
+            Err(
+                err @ (ConfyError::DirectoryCreationFailed(_)
+                | ConfyError::WriteConfigurationFileError(_)
+                | ConfyError::OpenConfigurationFileError(_)),
+            ) => {
+                log::warn!("failed to create the config file, using the default config: {err}");
+                Ok(Default::default())
+            }
+            Ok(mut config) => {
+                if let Err(err) = config.ranking_weights.validate() {
+                    log::warn!("{err}, falling back to the default ranking weights");
+                    config.ranking_weights = RankingWeights::default();
+                }
+                Ok(config)
+            }
+            result => result,
+        }
     }
 
     /// returns weather or not we should update the cache
-    /// This will return true, if the cache has not been created yet or is older than 30mn
-    pub fn should_update_db(&self) -> bool {
+    /// This will return true, if the cache has not been created yet or is older than
+    /// `refresh_after_minutes`, or the given override
+    pub fn should_update_db(&self, clock: &impl Clock, refresh_after_minutes: Option<u32>) -> bool {
+        let refresh_after = refresh_after_minutes.unwrap_or(self.refresh_after_minutes);
         match self.last_update_start_time {
             None => true,
-            Some(time) => time - chrono::Local::now() > chrono::Duration::minutes(30),
+            Some(time) => {
+                clock.now() - time.with_timezone(&chrono::Utc)
+                    >= chrono::Duration::minutes(refresh_after.into())
+            }
         }
     }
 
+    /// returns weather or not the cache holds enough rows to wait for the soft threshold, i.e
+    /// `healthy_cache_refresh_after_minutes`, before being updated
+    pub fn is_cache_healthy(&self, clock: &impl Clock, counts: &DBCounts) -> bool {
+        self.healthy_cache_min_rows > 0
+            && counts.repos + counts.crates >= self.healthy_cache_min_rows
+            && !self.should_update_db(clock, Some(self.healthy_cache_refresh_after_minutes))
+    }
+
+    /// returns whether the cache is stale, i.e never synced or last synced more than
+    /// `stale_warning_age_days` ago
+    pub fn stale_cache(&self, clock: &impl Clock) -> Option<StaleCache> {
+        if self.stale_warning_age_days == 0 {
+            return None;
+        }
+
+        let success_time = match self.last_update_success_time {
+            Some(success_time) => success_time,
+            None => return Some(StaleCache::NeverSynced),
+        };
+        let days = (clock.now() - success_time.with_timezone(&chrono::Utc)).num_days();
+        (days >= self.stale_warning_age_days.into()).then_some(StaleCache::DaysOld(days))
+    }
+
+    /// returns the timeout of the Github API calls of the searches
+    pub fn gh_search_timeout(&self) -> std::time::Duration {
+        timeout(self.gh_search_timeout_secs, |config| {
+            config.gh_search_timeout_secs
+        })
+    }
+
+    /// returns the timeout of each page of repositories fetched by a sync
+    pub fn gh_sync_timeout(&self) -> std::time::Duration {
+        timeout(self.gh_sync_timeout_secs, |config| {
+            config.gh_sync_timeout_secs
+        })
+    }
+
+    /// returns the timeout of the crates registry API calls
+    pub fn crate_search_timeout(&self) -> std::time::Duration {
+        timeout(self.crate_search_timeout_secs, |config| {
+            config.crate_search_timeout_secs
+        })
+    }
+
+    /// returns the enrich limit of the selected profile, or the configured one
+    pub fn enrich_limit(&self) -> usize {
+        SELECTED_PROFILE
+            .get()
+            .and_then(|profile| profile.enrich_limit)
+            .unwrap_or(self.enrich_limit)
+    }
+
+    /// returns the maximum number of repositories per owner, None when unlimited
+    pub fn max_per_owner(&self) -> Option<usize> {
+        self.max_per_owner.filter(|max| *max > 0)
+    }
+
     /// update and persist the 'last_update_start_time' timestamp
-    pub fn update_last_update_start_time(&mut self) -> Result<(), ConfyError> {
+    pub fn update_last_update_start_time(&mut self) {
         self.last_update_start_time = Some(chrono::Local::now());
         self.update()
     }
 
+    /// update and persist the 'last_update_success_time' timestamp
+    pub fn update_last_update_success_time(&mut self) {
+        self.last_update_success_time = Some(chrono::Local::now());
+        self.update()
+    }
+
+    /// update and persist the login of the authenticated Github user
+    pub fn update_viewer_login(&mut self, login: String) {
+        self.viewer_login = Some(login);
+        self.update()
+    }
+
     /// reset the stored 'last_update_start_time' timestamp
-    pub fn reset_last_update_start_time(&mut self) -> Result<(), ConfyError> {
+    pub fn reset_last_update_start_time(&mut self) {
         self.last_update_start_time = None;
         self.update()
     }
 
     /// persist the configuration to disk
-    fn update(&self) -> Result<(), ConfyError> {
-        confy::store(DEFAULT_CONFIG_NAME, self)
+    /// This is best-effort, e.g the config directory can be read-only on locked-down machines,
+    /// in which case the daemon is spawned more often, but the commands keep working
+    fn update(&self) {
+        if let Err(err) = confy::store(DEFAULT_CONFIG_NAME, self) {
+            log::warn!("failed to persist the config: {err}");
+        }
+    }
+}
+
+/// returns the configured timeout, or the default one when it's 0, since every call would time out
+fn timeout(secs: u64, default: impl Fn(&GhAlfredConfig) -> u64) -> std::time::Duration {
+    match secs {
+        0 => std::time::Duration::from_secs(default(&GhAlfredConfig::default())),
+        secs => std::time::Duration::from_secs(secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn clock() -> FixedClock {
+        FixedClock("2026-10-14T12:00:00Z".parse().unwrap())
+    }
+
+    fn started_minutes_ago(minutes: i64) -> GhAlfredConfig {
+        let start = clock().0 - chrono::Duration::minutes(minutes);
+        GhAlfredConfig {
+            last_update_start_time: Some(start.with_timezone(&chrono::Local)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn never_updated_cache_is_outdated() {
+        assert!(GhAlfredConfig::default().should_update_db(&clock(), None));
+    }
+
+    #[test]
+    fn cache_is_outdated_once_refresh_after_elapsed() {
+        assert!(!started_minutes_ago(29).should_update_db(&clock(), None));
+        assert!(started_minutes_ago(30).should_update_db(&clock(), None));
+        assert!(!started_minutes_ago(30).should_update_db(&clock(), Some(60)));
+    }
+
+    #[test]
+    fn cache_is_stale_after_stale_warning_age() {
+        let config = |days| GhAlfredConfig {
+            last_update_success_time: Some(
+                (clock().0 - chrono::Duration::days(days)).with_timezone(&chrono::Local),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(config(2).stale_cache(&clock()), None);
+        assert_eq!(
+            config(3).stale_cache(&clock()),
+            Some(StaleCache::DaysOld(3))
+        );
+    }
+
+    #[test]
+    fn zero_timeouts_fall_back_to_the_defaults() {
+        let config = GhAlfredConfig {
+            gh_search_timeout_secs: 0,
+            gh_sync_timeout_secs: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.gh_search_timeout(),
+            std::time::Duration::from_secs(10)
+        );
+        assert_eq!(config.gh_sync_timeout(), std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn never_synced_cache_is_stale() {
+        let config = |stale_warning_age_days| GhAlfredConfig {
+            stale_warning_age_days,
+            ..Default::default()
+        };
+        assert_eq!(
+            config(3).stale_cache(&clock()),
+            Some(StaleCache::NeverSynced)
+        );
+        assert_eq!(config(0).stale_cache(&clock()), None);
     }
 }
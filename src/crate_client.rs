@@ -1,10 +1,149 @@
 //! Client to query the crates.io API
 use reqwest::header::{self, HeaderMap, HeaderValue};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex, time::Duration};
+
+use crate::{
+    config::GhAlfredConfig,
+    http_client::{ApiError, HttpClient, HttpRequest, HttpResponse},
+    ranking,
+};
+
+/// Default registry, used when `CRATES_REGISTRY_URL` is not set or invalid
+const DEFAULT_REGISTRY_URL: &str = "https://crates.io";
+
+/// Number of looser searches run to suggest crates, when a search has no results
+const SUGGESTION_QUERIES: usize = 3;
+
+/// Maximum number of suggested crates
+const SUGGESTIONS_LIMIT: usize = 3;
+
+/// Number of crates of each page of the top crates, the maximum allowed by crates.io
+const TOP_CRATES_PAGE_SIZE: usize = 100;
+
+/// Name of the file persisting the circuit breaker of the registry between the searches
+const CIRCUIT_CONFIG_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "-registry-circuit");
+
+/// Number of consecutive failures of the registry opening the circuit
+const CIRCUIT_FAILURES: u32 = 3;
+
+/// Cooldown after the circuit opens, doubled by every failure after it
+const CIRCUIT_COOLDOWN_SECS: i64 = 30;
+
+/// Maximum cooldown of the circuit
+const CIRCUIT_MAX_COOLDOWN_SECS: i64 = 10 * 60;
 
 /// A crate.io client
 pub struct CrateClient {
-    client: reqwest::Client,
+    client: HttpClient,
+    /// base url of the crates.io compatible registry
+    registry_url: String,
+    /// extra fields extracted from the search results, keyed by variable name
+    extra_fields: BTreeMap<String, String>,
+    /// timeout of the calls to the registry
+    timeout: Duration,
+    /// skips the calls while the registry keeps failing
+    circuit: Circuit,
+}
+
+/// error returned instead of calling the registry while its circuit is open
+#[derive(Debug)]
+pub struct CircuitOpen {
+    /// when the registry is called again
+    pub until: chrono::DateTime<chrono::Utc>,
+}
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "crates.io is unavailable, retrying after {}",
+            self.until.with_timezone(&chrono::Local).format("%H:%M:%S")
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+/// persisted state of the circuit breaker
+#[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct CircuitState {
+    /// number of consecutive failed calls
+    failures: u32,
+    /// the calls are skipped until this time
+    open_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A circuit breaker, opened after `CIRCUIT_FAILURES` consecutive failures of the registry
+/// Each search is a new process, so the state is persisted, and only written when it changes
+struct Circuit {
+    state: Mutex<CircuitState>,
+    /// file persisting the state, the `CIRCUIT_CONFIG_NAME` config when None
+    path: Option<PathBuf>,
+}
+
+impl Circuit {
+    /// load the persisted circuit, a missing or invalid state is closed
+    fn load() -> Self {
+        Self::with_state(confy::load(CIRCUIT_CONFIG_NAME), None)
+    }
+
+    /// load the circuit persisted in the given file, a missing or invalid state is closed
+    #[cfg(test)]
+    fn load_path(path: PathBuf) -> Self {
+        Self::with_state(confy::load_path(&path), Some(path))
+    }
+
+    fn with_state(state: Result<CircuitState, confy::ConfyError>, path: Option<PathBuf>) -> Self {
+        let state = state
+            .map_err(|err| log::warn!("failed to load the registry circuit: {err:?}"))
+            .unwrap_or_default();
+        Self {
+            state: Mutex::new(state),
+            path,
+        }
+    }
+
+    /// fails with `CircuitOpen` while the cooldown is running
+    fn check(&self) -> Result<(), CircuitOpen> {
+        let state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        match state.open_until {
+            Some(until) if until > chrono::Utc::now() => Err(CircuitOpen { until }),
+            _ => Ok(()),
+        }
+    }
+
+    /// record the outcome of a call, the cooldown doubles with the failures after the opening
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if !failed {
+            if state.failures == 0 {
+                return;
+            }
+            *state = CircuitState::default();
+        } else {
+            state.failures += 1;
+            if state.failures >= CIRCUIT_FAILURES {
+                let doublings = (state.failures - CIRCUIT_FAILURES).min(16);
+                let cooldown = (CIRCUIT_COOLDOWN_SECS << doublings).min(CIRCUIT_MAX_COOLDOWN_SECS);
+                let until = chrono::Utc::now() + chrono::Duration::seconds(cooldown);
+                log::warn!(
+                    "registry failed {} times, skip it until {until}",
+                    state.failures
+                );
+                state.open_until = Some(until);
+            }
+        }
+
+        let stored = match &self.path {
+            Some(path) => confy::store_path(path, &*state),
+            None => confy::store(CIRCUIT_CONFIG_NAME, &*state),
+        };
+        if let Err(err) = stored {
+            log::warn!("failed to store the registry circuit: {err:?}");
+        }
+    }
 }
 
 /// response from the crates.io search API
@@ -13,46 +152,692 @@ struct CrateSearchResponse {
     crates: Vec<CrateSearchItem>,
 }
 
-/// response item from the crates.io search API
+/// response from the crates.io search API, only keeping the number of matches
+#[derive(Deserialize)]
+struct CrateCountResponse {
+    meta: CrateCountMeta,
+}
+
+/// metadata of a crates.io search
+#[derive(Deserialize)]
+struct CrateCountMeta {
+    total: u64,
+}
+
+/// response from the crates.io summary API, only keeping the lists of crates worth caching
+#[derive(Deserialize)]
+struct CrateSummaryResponse {
+    just_updated: Vec<CrateSearchItem>,
+    most_downloaded: Vec<CrateSearchItem>,
+}
+
+/// response from the crates.io crate API
 #[derive(Deserialize)]
+struct CrateResponse {
+    #[serde(rename = "crate")]
+    krate: CrateSearchItem,
+    #[serde(default)]
+    versions: Vec<CrateVersion>,
+}
+
+/// response item from the crates.io search API
+#[derive(Deserialize, Default, Clone)]
 pub struct CrateSearchItem {
     pub name: String,
+    /// cached by the update-db summary refresh, and when the top results are enriched
+    #[serde(default)]
+    pub description: Option<String>,
+    /// the snippet of the description matching the search, when the name itself does not match
+    #[serde(skip)]
+    pub matched_description: Option<String>,
+    #[serde(default)]
+    pub downloads: Option<u64>,
+    #[serde(default)]
+    pub max_version: Option<String>,
+    #[serde(default)]
+    pub max_stable_version: Option<String>,
+    #[serde(default)]
+    pub newest_version: Option<String>,
+    #[serde(default)]
+    pub repository: Option<String>,
+    #[serde(default)]
+    pub documentation: Option<String>,
+    /// RFC 3339 timestamp of the last update of the crate, only available from the API
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    /// whether the newest version was yanked, only available from the crate details
+    #[serde(skip)]
+    pub newest_version_yanked: Option<bool>,
+    /// feature flags of the latest version, only available from the crate details
+    #[serde(skip)]
+    pub features: Option<Vec<String>>,
+    /// minimum supported Rust version of the latest version, only available from the crate details
+    #[serde(skip)]
+    pub rust_version: Option<String>,
+    /// Rust edition of the latest version, only available from the crate details
+    #[serde(skip)]
+    pub edition: Option<String>,
+    /// extra fields of the search result, mapped to Alfred variables by `crate_extra_fields`
+    #[serde(skip)]
+    pub extra_variables: BTreeMap<String, String>,
+    /// daily downloads of the last 90 days, oldest first, only fetched when enriching the item
+    #[serde(skip)]
+    pub downloads_series: Option<Vec<u64>>,
+    /// users and teams owning the crate, only fetched when enriching the item
+    #[serde(skip)]
+    pub owners: Option<Vec<CrateOwner>>,
+}
+
+impl CrateSearchItem {
+    /// whether the fields only returned by the crate details are missing, e.g for the cached and
+    /// the searched items
+    pub fn is_missing_details(&self) -> bool {
+        self.description.is_none() || self.features.is_none()
+    }
+
+    /// returns the version to depend on, i.e the stable version when the newest one was yanked
+    pub fn recommended_version(&self) -> Option<&str> {
+        match self.newest_version_yanked {
+            Some(true) => self.max_stable_version.as_deref(),
+            _ => self.max_version.as_deref(),
+        }
+    }
+}
+
+/// a published version of a crate
+#[derive(Deserialize)]
+pub struct CrateVersion {
+    pub num: String,
+    pub yanked: bool,
+    /// RFC 3339 timestamp of the publication of the version
+    #[serde(default)]
+    pub created_at: Option<String>,
+    /// the minimum supported Rust version, only published by the recent crates
+    #[serde(default)]
+    rust_version: Option<String>,
+    /// the Rust edition, only published by the recent crates
+    #[serde(default)]
+    edition: Option<String>,
+    /// the feature flags of the version, and the features or dependencies they enable
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+}
+
+/// response from the crates.io dependencies API
+#[derive(Deserialize)]
+struct CrateDependenciesResponse {
+    dependencies: Vec<CrateDependency>,
+}
+
+/// kind of a crate dependency
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyKind {
+    Normal,
+    Build,
+    Dev,
+}
+
+impl DependencyKind {
+    /// returns the name of the kind, as in the Cargo.toml sections
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Build => "build",
+            Self::Dev => "dev",
+        }
+    }
+}
+
+/// a direct dependency of a crate version
+#[derive(Deserialize)]
+pub struct CrateDependency {
+    /// name of the dependency crate
+    pub crate_id: String,
+    /// version requirement, e.g `^1.0`
+    pub req: String,
+    pub kind: DependencyKind,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// response from the crates.io owners API
+#[derive(Deserialize)]
+struct CrateOwnersResponse {
+    users: Vec<CrateOwner>,
+}
+
+/// kind of a crate owner
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrateOwnerKind {
+    User,
+    /// a Github team, its login looks like `github:org:team`
+    Team,
+}
+
+/// a user or team owning a crate
+#[derive(Deserialize, Clone)]
+pub struct CrateOwner {
+    pub login: String,
+    pub kind: CrateOwnerKind,
+    /// profile page of the owner, e.g its Github page
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl CrateOwner {
+    /// returns the profile page of the owner, falling back to its crates.io page
+    pub fn profile_url(&self) -> String {
+        match (&self.url, self.kind) {
+            (Some(url), _) => url.clone(),
+            (None, CrateOwnerKind::User) => format!("https://crates.io/users/{}", self.login),
+            (None, CrateOwnerKind::Team) => format!("https://crates.io/teams/{}", self.login),
+        }
+    }
+}
+
+/// response from the crates.io downloads API
+#[derive(Deserialize)]
+struct CrateDownloadsResponse {
+    version_downloads: Vec<CrateDownloads>,
+    meta: CrateDownloadsMeta,
+}
+
+/// downloads not attributed to the recent versions
+#[derive(Deserialize)]
+struct CrateDownloadsMeta {
+    extra_downloads: Vec<CrateDownloads>,
+}
+
+/// number of downloads of a given day
+#[derive(Deserialize)]
+struct CrateDownloads {
+    date: String,
+    downloads: u64,
+}
+
+/// returns the crates.io page url of the given crate
+pub fn crate_url(name: &str) -> String {
+    format!("https://crates.io/crates/{name}")
+}
+
+/// returns weather or not the given string is a valid crates.io crate name
+/// i.e it starts with a letter and only contains alphanumeric characters, '-' or '_'
+pub fn is_valid_crate_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    name.len() <= 64
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// returns the registry base url from the CRATES_REGISTRY_URL environment variable, falling back
+/// to crates.io when it's missing or is not a valid http(s) url
+fn registry_url() -> String {
+    let url = match std::env::var("CRATES_REGISTRY_URL") {
+        Ok(url) => url,
+        Err(_) => return DEFAULT_REGISTRY_URL.to_string(),
+    };
+
+    match reqwest::Url::parse(&url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+            url.trim_end_matches('/').to_string()
+        }
+        _ => {
+            log::warn!("invalid CRATES_REGISTRY_URL {url:?}, falling back to crates.io");
+            DEFAULT_REGISTRY_URL.to_string()
+        }
+    }
 }
 
 impl CrateClient {
     /// create a new crates.io client
+    /// The registry can be replaced by any crates.io compatible registry with the
+    /// CRATES_REGISTRY_URL environment variable, and authorized with CRATES_REGISTRY_TOKEN
     pub async fn create() -> anyhow::Result<Self> {
-        let default_headers =
+        let mut default_headers =
             HeaderMap::from_iter([(header::ACCEPT, HeaderValue::from_static("application/json"))]);
 
-        let client = reqwest::Client::builder()
-            .default_headers(default_headers)
+        if let Ok(token) = std::env::var("CRATES_REGISTRY_TOKEN") {
+            let mut value = HeaderValue::from_str(&token)?;
+            value.set_sensitive(true);
+            default_headers.insert(header::AUTHORIZATION, value);
+        }
+
+        // compressed responses are transparently decompressed, unless disabled for debugging
+        let config = GhAlfredConfig::load()?;
+        let builder = reqwest::Client::builder()
             .user_agent(env!("CARGO_PKG_NAME"))
-            .build()?;
+            .gzip(config.compress_responses)
+            .deflate(config.compress_responses);
+        let client = HttpClient::build(builder, default_headers)?;
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            registry_url: registry_url(),
+            timeout: config.crate_search_timeout(),
+            extra_fields: config.crate_extra_fields,
+            circuit: Circuit::load(),
+        })
+    }
+
+    /// send the request, unless the circuit is open, recording whether the registry failed
+    async fn send(&self, request: HttpRequest) -> anyhow::Result<HttpResponse> {
+        self.circuit.check()?;
+        let result = request.send().await;
+        self.circuit.record(match &result {
+            Ok(response) => {
+                response.status().is_server_error()
+                    || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(_) => true,
+        });
+        Ok(result?)
+    }
+
+    /// returns the url of the given registry API path
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/api/v1/{path}", self.registry_url)
+    }
+
+    /// returns the url of the API path of the given crate, e.g `crates/{name}/owners`, each
+    /// segment is percent-encoded, so that an invalid name or version can't reach another path
+    fn crate_api_url(&self, segments: &[&str]) -> anyhow::Result<reqwest::Url> {
+        // the dot segments are not encoded, but resolved against the previous segments
+        if let Some(segment) = segments
+            .iter()
+            .find(|segment| matches!(**segment, "." | ".."))
+        {
+            anyhow::bail!("invalid crate path segment {segment:?}");
+        }
+
+        let mut url = reqwest::Url::parse(&self.api_url("crates"))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::format_err!("invalid registry url {}", self.registry_url))?
+            .extend(segments);
+        Ok(url)
     }
 
     /// search for crates matching the given filter
     pub async fn search_crate(&self, filter: &str) -> anyhow::Result<Vec<CrateSearchItem>> {
-        log::info!("querying crates.io crate matching {filter}");
-        let response = self
+        log::info!("querying {} crate matching {filter}", self.registry_url);
+        self.crates_page(&[("page", "1"), ("per_page", "5"), ("q", filter)])
+            .await
+    }
+
+    /// fetch the `count` most downloaded crates, one page at a time
+    pub async fn top_crates(&self, count: usize) -> anyhow::Result<Vec<CrateSearchItem>> {
+        log::info!("querying {} top {count} crates", self.registry_url);
+        // the pages all have the same size, as the offset of a page is its number times its size
+        let per_page = TOP_CRATES_PAGE_SIZE.min(count).to_string();
+        let mut crates = Vec::new();
+        for page in 1.. {
+            if crates.len() >= count {
+                break;
+            }
+
+            let page = page.to_string();
+            let items = self
+                .crates_page(&[
+                    ("page", &page),
+                    ("per_page", &per_page),
+                    ("sort", "downloads"),
+                ])
+                .await?;
+
+            if items.is_empty() {
+                break;
+            }
+            crates.extend(items);
+        }
+        crates.truncate(count);
+        Ok(crates)
+    }
+
+    /// fetch one page of the crates search with the given query parameters
+    async fn crates_page(&self, query: &[(&str, &str)]) -> anyhow::Result<Vec<CrateSearchItem>> {
+        let request = self
             .client
-            .get("https://crates.io/api/v1/crates")
-            .query(&[("page", "1"), ("per_page", "5"), ("q", filter)])
-            .send()
-            .await?;
+            .get(self.api_url("crates"))
+            .query(query)
+            .timeout(self.timeout);
+        let response = self.send(request).await?;
+
+        let response = ApiError::check(response, "search crate").await?;
 
-        if !response.status().is_success() {
-            return Err(anyhow::format_err!(
-                "Failed to search crate: {}, {}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            ));
+        // the generic representation is only needed to extract the extra fields
+        if self.extra_fields.is_empty() {
+            return Ok(response.json::<CrateSearchResponse>().await?.crates);
         }
 
-        let crates = response.json::<CrateSearchResponse>().await?.crates;
+        let response = response.json::<serde_json::Value>().await?;
+        let crates = response
+            .get("crates")
+            .and_then(serde_json::Value::as_array)
+            .ok_or_else(|| anyhow::format_err!("missing crates in the search response"))?;
+
+        crates
+            .iter()
+            .map(|value| {
+                let mut item = CrateSearchItem::deserialize(value)?;
+                item.extra_variables = self.extra_variables(value);
+                Ok(item)
+            })
+            .collect()
+    }
+
+    /// extract the configured extra fields from the JSON representation of a crate
+    fn extra_variables(&self, value: &serde_json::Value) -> BTreeMap<String, String> {
+        self.extra_fields
+            .iter()
+            .filter_map(|(key, path)| {
+                let field = match value.pointer(path)? {
+                    serde_json::Value::String(field) => field.clone(),
+                    field => field.to_string(),
+                };
+                Some((key.clone(), field))
+            })
+            .collect()
+    }
 
+    /// returns the names of the crates closest to the given filter, found with looser searches
+    /// of the filter minus one character, e.g `serde_js` for `serde_jsn`
+    pub async fn suggest_crates(&self, filter: &str) -> anyhow::Result<Vec<String>> {
+        let chars = filter.chars().collect::<Vec<_>>();
+        let mut queries = (0..chars.len())
+            .rev()
+            .map(|index| {
+                let mut query = chars.clone();
+                query.remove(index);
+                query.into_iter().collect::<String>()
+            })
+            .filter(|query| !query.trim().is_empty())
+            .collect::<Vec<_>>();
+        queries.dedup();
+        queries.truncate(SUGGESTION_QUERIES);
+
+        let results =
+            futures::future::try_join_all(queries.iter().map(|query| self.search_crate(query)))
+                .await?;
+
+        let mut names = results
+            .into_iter()
+            .flatten()
+            .map(|item| item.name)
+            .filter(|name| name != filter)
+            .collect::<Vec<_>>();
+        names.sort_by_cached_key(|name| (ranking::edit_distance(name, filter), name.clone()));
+        names.dedup();
+        names.truncate(SUGGESTIONS_LIMIT);
+        Ok(names)
+    }
+
+    /// fetch the crates just updated, and the most downloaded ones, to refresh the cache
+    pub async fn summary_crates(&self) -> anyhow::Result<Vec<CrateSearchItem>> {
+        log::info!("querying {} summary", self.registry_url);
+        let request = self
+            .client
+            .get(self.api_url("summary"))
+            .timeout(self.timeout);
+        let response = self.send(request).await?;
+
+        let response = ApiError::check(response, "fetch summary").await?;
+
+        let summary = response.json::<CrateSummaryResponse>().await?;
+        let mut crates = summary.just_updated;
+        for item in summary.most_downloaded {
+            if !crates.iter().any(|other| other.name == item.name) {
+                crates.push(item);
+            }
+        }
         Ok(crates)
     }
+
+    /// count the crates matching the given filter
+    pub async fn count_crates(&self, filter: &str) -> anyhow::Result<u64> {
+        log::info!("counting {} crates matching {filter}", self.registry_url);
+        let request = self
+            .client
+            .get(self.api_url("crates"))
+            .query(&[("page", "1"), ("per_page", "1"), ("q", filter)])
+            .timeout(self.timeout);
+        let response = self.send(request).await?;
+
+        let response = ApiError::check(response, "count crates").await?;
+
+        Ok(response.json::<CrateCountResponse>().await?.meta.total)
+    }
+
+    /// fetch the details of the given crate
+    pub async fn crate_details(&self, name: &str) -> anyhow::Result<CrateSearchItem> {
+        self.find_crate(name)
+            .await?
+            .ok_or_else(|| anyhow::format_err!("crate {name} not found"))
+    }
+
+    /// fetch the details of the given crate, returns None if the crate does not exist
+    pub async fn find_crate(&self, name: &str) -> anyhow::Result<Option<CrateSearchItem>> {
+        Ok(self.find_crate_versions(name).await?.map(|(item, _)| item))
+    }
+
+    /// fetch the details of the given crate, and its published versions, most recent first,
+    /// returns None if the crate does not exist
+    pub async fn find_crate_versions(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<Option<(CrateSearchItem, Vec<CrateVersion>)>> {
+        log::info!("querying {} for crate {name} details", self.registry_url);
+        let request = self
+            .client
+            .get(self.crate_api_url(&[name])?)
+            .timeout(self.timeout);
+        let response = self.send(request).await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let response = ApiError::check(response, "fetch crate details").await?;
+
+        let value = response.json::<serde_json::Value>().await?;
+        let CrateResponse {
+            mut krate,
+            mut versions,
+        } = CrateResponse::deserialize(&value)?;
+        if let Some(krate_value) = value.get("crate") {
+            krate.extra_variables = self.extra_variables(krate_value);
+        }
+
+        // features, MSRV and edition of the version displayed with the crate
+        if let Some(latest) = versions
+            .iter()
+            .find(|version| Some(&version.num) == krate.max_version.as_ref())
+        {
+            krate.features = Some(latest.features.keys().cloned().collect());
+            krate.rust_version = latest.rust_version.clone();
+            krate.edition = latest.edition.clone();
+        }
+
+        krate.newest_version_yanked = krate.newest_version.as_ref().map(|newest| {
+            versions
+                .iter()
+                .any(|version| &version.num == newest && version.yanked)
+        });
+
+        // the timestamps share the same format, so they sort chronologically
+        versions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(Some((krate, versions)))
+    }
+
+    /// fetch the daily downloads of the given crate over the last 90 days, oldest first
+    pub async fn downloads_series(&self, name: &str) -> anyhow::Result<Vec<u64>> {
+        log::info!("querying {} for crate {name} downloads", self.registry_url);
+        let request = self
+            .client
+            .get(self.crate_api_url(&[name, "downloads"])?)
+            .timeout(self.timeout);
+        let response = self.send(request).await?;
+
+        let response = ApiError::check(response, "fetch crate downloads").await?;
+
+        let response = response.json::<CrateDownloadsResponse>().await?;
+
+        // sum the downloads of all versions, by day
+        let mut series = BTreeMap::<String, u64>::new();
+        for day in response
+            .version_downloads
+            .into_iter()
+            .chain(response.meta.extra_downloads)
+        {
+            *series.entry(day.date).or_default() += day.downloads;
+        }
+
+        Ok(series.into_values().collect())
+    }
+
+    /// fetch the direct dependencies of the given crate version, by kind and name
+    pub async fn dependencies(
+        &self,
+        name: &str,
+        version: &str,
+    ) -> anyhow::Result<Vec<CrateDependency>> {
+        log::info!(
+            "querying {} for crate {name} {version} dependencies",
+            self.registry_url
+        );
+        let request = self
+            .client
+            .get(self.crate_api_url(&[name, version, "dependencies"])?)
+            .timeout(self.timeout);
+        let response = self.send(request).await?;
+
+        let response = ApiError::check(response, "fetch crate dependencies").await?;
+
+        let mut dependencies = response
+            .json::<CrateDependenciesResponse>()
+            .await?
+            .dependencies;
+        dependencies.sort_by(|a, b| (a.kind, &a.crate_id).cmp(&(b.kind, &b.crate_id)));
+        Ok(dependencies)
+    }
+
+    /// fetch the users and teams owning the given crate
+    pub async fn owners(&self, name: &str) -> anyhow::Result<Vec<CrateOwner>> {
+        log::info!("querying {} for crate {name} owners", self.registry_url);
+        let request = self
+            .client
+            .get(self.crate_api_url(&[name, "owners"])?)
+            .timeout(self.timeout);
+        let response = self.send(request).await?;
+
+        let response = ApiError::check(response, "fetch crate owners").await?;
+
+        Ok(response.json::<CrateOwnersResponse>().await?.users)
+    }
+
+    /// fetch the details the given item is missing, e.g whether its newest version was yanked,
+    /// its recent downloads and its owners
+    pub async fn enrich_crate(&self, item: CrateSearchItem) -> anyhow::Result<CrateSearchItem> {
+        let mut item = match item.is_missing_details() {
+            true => {
+                // the extra fields read from the search response win over the details ones
+                let mut details = self.crate_details(&item.name).await?;
+                details.extra_variables.extend(item.extra_variables);
+                CrateSearchItem {
+                    matched_description: item.matched_description,
+                    ..details
+                }
+            }
+            false => item,
+        };
+
+        item.downloads_series = self
+            .downloads_series(&item.name)
+            .await
+            .map_err(|err| log::warn!("failed to fetch downloads series: {err:?}"))
+            .ok();
+
+        item.owners = self
+            .owners(&item.name)
+            .await
+            .map_err(|err| log::warn!("failed to fetch crate owners: {err:?}"))
+            .ok();
+
+        Ok(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a closed circuit persisted in a temp file of the given test
+    fn circuit(test: &str) -> (Circuit, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "{}-test-{}-{test}.toml",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        (Circuit::load_path(path.clone()), path)
+    }
+
+    /// record the given number of failures
+    fn fail(circuit: &Circuit, failures: u32) {
+        (0..failures).for_each(|_| circuit.record(true));
+    }
+
+    /// returns the remaining cooldown of the circuit, in seconds
+    fn cooldown_secs(circuit: &Circuit) -> i64 {
+        let until = circuit.check().unwrap_err().until;
+        // round the time elapsed since the circuit opened
+        (until - chrono::Utc::now() + chrono::Duration::milliseconds(500)).num_seconds()
+    }
+
+    #[test]
+    fn circuit_stays_closed_below_the_failures_threshold() {
+        let (circuit, path) = circuit("closed");
+        fail(&circuit, CIRCUIT_FAILURES - 1);
+        assert!(circuit.check().is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn circuit_opens_at_the_failures_threshold() {
+        let (circuit, path) = circuit("open");
+        fail(&circuit, CIRCUIT_FAILURES);
+        assert_eq!(cooldown_secs(&circuit), CIRCUIT_COOLDOWN_SECS);
+
+        // the next search sees the open circuit
+        assert!(Circuit::load_path(path.clone()).check().is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn circuit_cooldown_doubles_up_to_the_maximum() {
+        let (circuit, path) = circuit("cooldown");
+        fail(&circuit, CIRCUIT_FAILURES + 1);
+        assert_eq!(cooldown_secs(&circuit), 2 * CIRCUIT_COOLDOWN_SECS);
+
+        circuit.record(true);
+        assert_eq!(cooldown_secs(&circuit), 4 * CIRCUIT_COOLDOWN_SECS);
+
+        fail(&circuit, 20);
+        assert_eq!(cooldown_secs(&circuit), CIRCUIT_MAX_COOLDOWN_SECS);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn circuit_resets_on_success() {
+        let (circuit, path) = circuit("reset");
+        fail(&circuit, CIRCUIT_FAILURES);
+        circuit.record(false);
+        assert!(circuit.check().is_ok());
+
+        // the failures before the success don't count towards the next opening
+        fail(&circuit, CIRCUIT_FAILURES - 1);
+        assert!(circuit.check().is_ok());
+        assert!(Circuit::load_path(path.clone()).check().is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -1,55 +1,693 @@
 //! Client to interact with the Sqlite database
 use anyhow::Context;
 use futures::{Stream, TryStreamExt};
-use sqlx::{ConnectOptions, QueryBuilder, SqlitePool};
-use std::{env, str::FromStr};
+use sqlx::{
+    migrate::{MigrateError, Migrator},
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    ConnectOptions, Connection, QueryBuilder, SqlitePool,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use unicode_normalization::UnicodeNormalization;
 
-use crate::{crate_client::CrateSearchItem, gh_client::GHApiRepoSearchItem};
+use crate::{
+    config::GhAlfredConfig,
+    crate_client::CrateSearchItem,
+    gh_client::{GHApiRepoSearchItem, RepoSource},
+    spawn_daemon::UpdateLock,
+};
+
+/// Migrations embedded from the `migrations` folder, applied in order when the client is created
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// normalize names and filters to NFC, so that composed and decomposed forms match each other
+fn normalize(value: &str) -> String {
+    value.nfc().collect()
+}
+
+/// returns the path of the database file of the given sqlite url, if it's not an in-memory database
+fn database_path(url: &str) -> Option<PathBuf> {
+    let path = url
+        .trim_start_matches("sqlite:")
+        .trim_start_matches("//")
+        .split('?')
+        .next()?;
+
+    (!path.is_empty() && path != ":memory:").then(|| PathBuf::from(path))
+}
+
+/// error returned when the integrity check of a database fails
+#[derive(Debug)]
+pub struct CorruptDatabase(String);
+
+impl std::fmt::Display for CorruptDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "corrupt database: {}", self.0)
+    }
+}
+
+impl std::error::Error for CorruptDatabase {}
+
+/// returns true if the error reports a corrupt database file
+fn is_corruption(err: &anyhow::Error) -> bool {
+    if err.is::<CorruptDatabase>() {
+        return true;
+    }
+
+    // SQLITE_CORRUPT and SQLITE_NOTADB, the extended codes share their lower byte
+    err.chain().any(|cause| {
+        let db_err = match cause.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::Database(db_err)) => db_err,
+            _ => match cause.downcast_ref::<MigrateError>() {
+                Some(MigrateError::Execute(sqlx::Error::Database(db_err))) => db_err,
+                _ => return false,
+            },
+        };
+
+        db_err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 11 | 26))
+    })
+}
+
+/// Maximum number of items returned by the searches, matching the LIMIT of their queries
+const SEARCH_LIMIT: usize = 5;
+
+/// Number of names inserted by each statement of a seed, below the sqlite bound parameters limit
+const SEED_BATCH_SIZE: usize = 500;
+
+/// merge the results of several databases, dropping the duplicated names, sorted like the
+/// queries: the shorter names first, since they are usually the closest matches, then by name
+fn merge_by_name<T>(
+    items: impl IntoIterator<Item = T>,
+    name: impl Fn(&T) -> &str,
+    limit: usize,
+) -> Vec<T> {
+    let mut merged = Vec::<T>::new();
+    for item in items {
+        if !merged.iter().any(|other| name(other) == name(&item)) {
+            merged.push(item);
+        }
+    }
+
+    merged.sort_by_cached_key(|item| (name(item).chars().count(), name(item).to_string()));
+    merged.truncate(limit);
+    merged
+}
+
+/// Number of characters of the description kept before the first matched word of a snippet
+const SNIPPET_CONTEXT: usize = 20;
+
+/// Maximum number of characters of a description snippet
+const SNIPPET_LENGTH: usize = 60;
+
+/// returns the part of the description around the matched words, or the first one of them when
+/// they are apart, ellipsized
+fn description_snippet(description: &str, words: &[&str]) -> Option<String> {
+    // the lowercase description can have different char boundaries, e.g for `İ`, so the words
+    // are searched from each char of the description instead of in its lowercase copy
+    let char_position = |word: &str| {
+        let word = word.to_lowercase();
+        description
+            .char_indices()
+            .position(|(index, _)| description[index..].to_lowercase().starts_with(&word))
+    };
+    let position = char_position(&words.join(" ")).or_else(|| char_position(words.first()?))?;
+    let start = position.saturating_sub(SNIPPET_CONTEXT);
+    let chars = description.chars().collect::<Vec<_>>();
+    let end = (start + SNIPPET_LENGTH).min(chars.len());
+
+    let mut snippet = chars[start..end].iter().collect::<String>();
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    Some(snippet)
+}
 
 /// Utility struct to read / write from the Sqlite database
 #[derive(Clone)]
 pub struct DBClient {
     pool: SqlitePool,
+    /// pools of the extra read-only caches, whose results are merged into the searches
+    extra_pools: Vec<SqlitePool>,
+    /// when set, writes are only logged and never executed
+    dry_run: bool,
 }
 
 /// event emitted when the database is updated
 #[derive(Default, Debug, Clone)]
-pub struct DBUpdateEvent;
+pub struct DBUpdateEvent {
+    /// number of repositories saved by this update
+    pub repos: usize,
+}
+
+/// number of rows stored in the cache tables
+#[derive(Default, Debug, Clone)]
+pub struct DBCounts {
+    pub repos: i64,
+    pub crates: i64,
+}
+
+/// Optional filters applied when searching the cached repositories
+#[derive(Default, Debug)]
+pub struct RepoFilters {
+    /// only return the repositories pushed after this RFC 3339 timestamp
+    pub pushed_since: Option<String>,
+    /// only return the repositories tagged with this tag
+    pub tag: Option<String>,
+    /// skip the forked repositories
+    pub hide_forks: bool,
+    /// only return the repositories owned by the authenticated user, i.e synced as `owned`
+    pub owned_only: bool,
+}
+
+/// The cache tables searchable by name
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum NamesTable {
+    Repos,
+    Crates,
+}
 
 impl DBClient {
-    /// create a new DB client
+    /// create a new DB client, connected to the DATABASE_URL database
+    /// and to the configured extra databases
     pub async fn create() -> anyhow::Result<Self> {
-        let url = &env::var("DATABASE_URL")?;
+        Self::create_updating(None).await
+    }
+
+    /// create a new DB client, like `create`, for the process holding the update lock
+    /// A corrupt database is only moved aside under the update lock, so that it's never renamed
+    /// while the background update still has it open
+    pub async fn create_updating(lock: Option<&UpdateLock>) -> anyhow::Result<Self> {
+        let url = env::var("DATABASE_URL")?;
+        let mut config = GhAlfredConfig::load()?;
+        let seed = |url: &str| match &config.seed_snapshot {
+            Some(snapshot) => Self::seed(url, snapshot),
+            None => Ok(()),
+        };
+
+        seed(&url)?;
+        let mut client = match Self::create_checked(&url).await {
+            Ok(client) => client,
+            Err(err) if config.repair_database && is_corruption(&err) => {
+                let acquired;
+                let _lock =
+                    match lock {
+                        Some(lock) => lock,
+                        None => match UpdateLock::try_acquire()? {
+                            Some(lock) => {
+                                acquired = lock;
+                                &acquired
+                            }
+                            None => return Err(err.context(
+                                "the database is being updated, it's repaired by the next update",
+                            )),
+                        },
+                    };
+                Self::move_aside(&url, &err)?;
+                seed(&url)?;
+
+                // the empty cache is filled again by the next sync
+                config.reset_last_update_start_time();
+                Self::create_with_url(&url).await?
+            }
+            Err(err) => return Err(err),
+        };
+
+        for url in config.extra_database_urls {
+            let extra = Self::open_read_only(&url)
+                .await
+                .with_context(|| format!("failed to open the extra database {url}"))?;
+            client.extra_pools.push(extra);
+        }
+        Ok(client)
+    }
+
+    /// create a new DB client, connected to the given database url, and check its integrity
+    async fn create_checked(url: &str) -> anyhow::Result<Self> {
+        let client = Self::create_with_url(url).await?;
+        if let Err(err) = client.check_integrity().await {
+            client.close().await;
+            return Err(err);
+        }
+        Ok(client)
+    }
+
+    /// copy the snapshot to the path of the database at the given url, if it doesn't exist yet
+    /// The migrations applied when the client is created bring an older snapshot up to date
+    fn seed(url: &str, snapshot: &Path) -> anyhow::Result<()> {
+        let path = match database_path(url) {
+            Some(path) if !path.exists() => path,
+            _ => return Ok(()),
+        };
+
+        if !snapshot.exists() {
+            log::warn!("the seed snapshot {} doesn't exist", snapshot.display());
+            return Ok(());
+        }
+
+        log::info!(
+            "seeding the database {} with the snapshot {}",
+            path.display(),
+            snapshot.display()
+        );
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::copy(snapshot, &path)
+            .with_context(|| format!("failed to copy the seed snapshot {}", snapshot.display()))?;
+        Ok(())
+    }
+
+    /// rename the corrupt database file at the given url, and its journals, out of the way
+    fn move_aside(url: &str, err: &anyhow::Error) -> anyhow::Result<()> {
+        let path = database_path(url)
+            .ok_or_else(|| anyhow::format_err!("can't repair the in-memory database {url}"))?;
+        let suffix = format!("corrupt-{}", chrono::Local::now().format("%Y%m%d%H%M%S"));
+        log::warn!(
+            "the database {} is corrupt, moving it aside and starting over: {err:?}",
+            path.display()
+        );
+
+        for journal in ["", "-wal", "-shm", "-journal"] {
+            let file = PathBuf::from(format!("{}{journal}", path.display()));
+            if file.exists() {
+                let backup = PathBuf::from(format!("{}.{suffix}", file.display()));
+                std::fs::rename(&file, &backup).with_context(|| {
+                    format!("failed to move the corrupt database {}", file.display())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// run the sqlite quick check, failing with `CorruptDatabase` on any reported problem
+    /// Unlike the full integrity check, it doesn't cross-check the indexes, so that it stays cheap
+    /// enough to run whenever a client is created
+    async fn check_integrity(&self) -> anyhow::Result<()> {
+        // the check itself fails on some damaged pages
+        let problems = sqlx::query_scalar::<_, String>("PRAGMA quick_check")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|err| match err {
+                sqlx::Error::Database(db_err) => CorruptDatabase(db_err.to_string()).into(),
+                err => anyhow::Error::from(err),
+            })?;
+
+        match problems.as_slice() {
+            [ok] if ok == "ok" => Ok(()),
+            _ => Err(CorruptDatabase(problems.join(", ")).into()),
+        }
+    }
 
-        let mut options = sqlx::sqlite::SqliteConnectOptions::from_str(url)?;
+    /// create a new DB client, connected to the given database url
+    pub async fn create_with_url(url: &str) -> anyhow::Result<Self> {
+        // sqlite creates the database file, but not its parent directories
+        if let Some(dir) = database_path(url).as_deref().and_then(Path::parent) {
+            std::fs::create_dir_all(dir).with_context(|| {
+                format!("failed to create the database directory {}", dir.display())
+            })?;
+        }
+
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        Self::create_with_options(options, SqlitePoolOptions::new()).await
+    }
+
+    /// open the existing database at the given url, without creating or migrating it, e.g for the
+    /// extra databases owned by other users
+    async fn open_read_only(url: &str) -> anyhow::Result<SqlitePool> {
+        let mut options = SqliteConnectOptions::from_str(url)?.read_only(true);
         options.disable_statement_logging();
+        Ok(SqlitePoolOptions::new().connect_with(options).await?)
+    }
+
+    /// create a new DB client, backed by an isolated in-memory database
+    pub async fn create_in_memory() -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")?;
+
+        // every connection opens its own in-memory database, so we keep a single one alive
+        let pool_options = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+
+        Self::create_with_options(options, pool_options).await
+    }
+
+    /// create a new DB client and apply the pending migrations
+    async fn create_with_options(
+        mut options: SqliteConnectOptions,
+        pool_options: SqlitePoolOptions,
+    ) -> anyhow::Result<Self> {
+        options.disable_statement_logging();
+
+        let pool = pool_options.connect_with(options).await?;
+        let client = Self {
+            pool,
+            extra_pools: Vec::new(),
+            dry_run: false,
+        };
+        client.migrate().await?;
+
+        Ok(client)
+    }
+
+    /// copy the cached rows of the database at the given url into this database, all at once
+    /// Only the columns of both schemas are copied, so that a snapshot created by an older or a
+    /// newer version can be imported without migrating it
+    pub async fn copy_from(&self, url: &str) -> anyhow::Result<()> {
+        let path = database_path(url)
+            .ok_or_else(|| anyhow::format_err!("can't copy the in-memory database {url}"))?;
+
+        // attached databases are bound to the connection, so the copy uses a single one
+        // the path is passed as a read-only URI, a plain path would be attached as in-memory too
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("ATTACH DATABASE ? AS source")
+            .bind(format!("file:{}?mode=ro", path.canonicalize()?.display()))
+            .execute(&mut conn)
+            .await?;
+
+        // databases can't be attached in a transaction, so it's only started once attached
+        let mut tx = conn.begin().await?;
+        for table in ["repos", "crates", "repo_tags", "repo_topics"] {
+            let columns = sqlx::query_scalar::<_, String>(
+                "SELECT name FROM pragma_table_info(?1, 'main')
+                INTERSECT SELECT name FROM pragma_table_info(?1, 'source')",
+            )
+            .bind(table)
+            .fetch_all(&mut tx)
+            .await?;
 
-        let pool = SqlitePool::connect_with(options).await?;
+            // e.g a table created by a later migration than the snapshot
+            if columns.is_empty() {
+                log::warn!("skip the {table} table, missing from the copied database");
+                continue;
+            }
+
+            let columns = columns
+                .iter()
+                .map(|column| format!("\"{column}\""))
+                .collect::<Vec<_>>()
+                .join(", ");
+            sqlx::query(&format!(
+                "INSERT OR REPLACE INTO {table} ({columns}) SELECT {columns} FROM source.{table}"
+            ))
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("failed to copy the {table} table"))?;
+        }
+        tx.commit().await?;
 
-        Ok(Self { pool })
+        sqlx::query("DETACH DATABASE source")
+            .execute(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    /// close all the connections of the pools
+    pub async fn close(&self) {
+        for pool in self.pools() {
+            pool.close().await;
+        }
+    }
+
+    /// returns the primary pool, followed by the pools of the extra databases
+    fn pools(&self) -> impl Iterator<Item = &SqlitePool> {
+        std::iter::once(&self.pool).chain(&self.extra_pools)
+    }
+
+    /// returns a client that only logs the writes it would perform
+    pub fn dry_run(self) -> Self {
+        Self {
+            dry_run: true,
+            ..self
+        }
+    }
+
+    /// returns the version of the last migration applied to the database, if any
+    async fn schema_version(&self) -> Option<i64> {
+        sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&self.pool)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// apply the pending migrations, so that caches created by older versions are upgraded in place
+    async fn migrate(&self) -> anyhow::Result<()> {
+        let version = self.schema_version().await;
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .context("failed to migrate the database")?;
+
+        let new_version = self.schema_version().await;
+        if new_version != version {
+            log::info!("Migrated database schema from {version:?} to {new_version:?}");
+        }
+
+        Ok(())
     }
 
     /// clear the database
     pub async fn clear(&self) -> anyhow::Result<()> {
         let sql = sqlx::query!("DELETE FROM repos");
         sql.execute(&self.pool).await?;
+        let sql = sqlx::query!("DELETE FROM repo_topics");
+        sql.execute(&self.pool).await?;
         Ok(())
     }
 
-    /// Search repositories matching the given query string
+    /// count the rows stored in the cache tables
+    pub async fn counts(&self) -> anyhow::Result<DBCounts> {
+        let repos = sqlx::query_scalar!("SELECT COUNT(*) FROM repos")
+            .fetch_one(&self.pool)
+            .await?;
+        let crates = sqlx::query_scalar!("SELECT COUNT(*) FROM crates")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(DBCounts {
+            repos: repos.into(),
+            crates: crates.into(),
+        })
+    }
+
+    /// Search repositories matching the given query string and filters
     pub async fn search_repositories(
         &self,
         filter: &str,
+        filters: &RepoFilters,
     ) -> anyhow::Result<impl Iterator<Item = GHApiRepoSearchItem>> {
         log::debug!("search repositories matching {filter}");
-        let filter = format!("%{}%", filter);
-        let recs = sqlx::query!("SELECT name FROM repos WHERE name like ? LIMIT 5", filter) // filter
+        let filter = format!("%{}%", normalize(filter));
+        let mut recs = Vec::new();
+        for pool in self.pools() {
+            recs.extend(
+                sqlx::query!(
+                    r#"SELECT name AS "name!", pushed_at, default_branch,
+                CASE WHEN name like ?1 THEN NULL ELSE (
+                    SELECT topic FROM repo_topics
+                    WHERE repo_topics.name = repos.name AND topic like ?1
+                    ORDER BY topic LIMIT 1
+                ) END AS "matched_topic?: String"
+            FROM repos
+            WHERE (name like ?1 OR name IN (SELECT name FROM repo_topics WHERE topic like ?1))
+            AND (?2 IS NULL OR pushed_at >= ?2)
+            AND (?3 IS NULL OR name IN (SELECT name FROM repo_tags WHERE tag = ?3))
+            AND (NOT ?4 OR is_fork = 0)
+            AND (NOT ?5 OR source = 'owned')
+            ORDER BY length(name) ASC, name ASC
+            LIMIT 5"#,
+                    filter,
+                    filters.pushed_since,
+                    filters.tag,
+                    filters.hide_forks,
+                    filters.owned_only
+                )
+                .fetch_all(pool)
+                .await?,
+            );
+        }
+
+        let repos = recs.into_iter().map(|repo| GHApiRepoSearchItem {
+            full_name: repo.name,
+            pushed_at: repo.pushed_at,
+            default_branch: repo.default_branch,
+            matched_topic: repo.matched_topic,
+            ..Default::default()
+        });
+        Ok(merge_by_name(repos, |repo| &repo.full_name, SEARCH_LIMIT).into_iter())
+    }
+
+    /// count the repositories matching the given query string, without building the items
+    /// A repository cached by several databases is counted once, as the searches merge them
+    pub async fn count_repos(&self, filter: &str, filters: &RepoFilters) -> anyhow::Result<i64> {
+        let filter = format!("%{}%", normalize(filter));
+        let mut names = HashSet::new();
+        for pool in self.pools() {
+            names.extend(
+                sqlx::query_scalar!(
+                    r#"SELECT name AS "name!" FROM repos
+            WHERE (name like ?1 OR name IN (SELECT name FROM repo_topics WHERE topic like ?1))
+            AND (?2 IS NULL OR pushed_at >= ?2)
+            AND (?3 IS NULL OR name IN (SELECT name FROM repo_tags WHERE tag = ?3))
+            AND (NOT ?4 OR is_fork = 0)
+            AND (NOT ?5 OR source = 'owned')"#,
+                    filter,
+                    filters.pushed_since,
+                    filters.tag,
+                    filters.hide_forks,
+                    filters.owned_only
+                )
+                .fetch_all(pool)
+                .await?,
+            );
+        }
+        Ok(i64::try_from(names.len())?)
+    }
+
+    /// count the crates matching the given query string, a crate cached by several databases is
+    /// counted once
+    pub async fn count_crates(&self, filter: &str) -> anyhow::Result<i64> {
+        let filter = format!("%{}%", normalize(filter));
+        let mut names = HashSet::new();
+        for pool in self.pools() {
+            names.extend(
+                sqlx::query_scalar!(
+                    r#"SELECT name AS "name!" FROM crates WHERE name like ?"#,
+                    filter
+                )
+                .fetch_all(pool)
+                .await?,
+            );
+        }
+        Ok(i64::try_from(names.len())?)
+    }
+
+    /// Search the names matching the given query string, without building the full items
+    pub async fn search_names(
+        &self,
+        table: NamesTable,
+        filter: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let filter = format!("%{}%", normalize(filter));
+        let sql_limit = i64::try_from(limit)?;
+        let mut names = Vec::new();
+        for pool in self.pools() {
+            names.extend(match table {
+                NamesTable::Repos => {
+                    sqlx::query_scalar!(
+                        r#"SELECT name AS "name!" FROM repos WHERE name like ?
+                        ORDER BY length(name) ASC, name ASC LIMIT ?"#,
+                        filter,
+                        sql_limit
+                    )
+                    .fetch_all(pool)
+                    .await?
+                }
+                NamesTable::Crates => {
+                    sqlx::query_scalar!(
+                        r#"SELECT name AS "name!" FROM crates WHERE name like ?
+                        ORDER BY length(name) ASC, name ASC LIMIT ?"#,
+                        filter,
+                        sql_limit
+                    )
+                    .fetch_all(pool)
+                    .await?
+                }
+            });
+        }
+
+        Ok(merge_by_name(names, String::as_str, limit))
+    }
+
+    /// returns the distinct names of the cached repositories of the given owner matching the
+    /// filters, e.g `tokio-rs/tokio`, merged over the databases
+    pub async fn owner_repo_names(
+        &self,
+        owner: &str,
+        filters: &RepoFilters,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let owner = normalize(owner);
+        let sql_limit = i64::try_from(limit)?;
+        let mut names = Vec::new();
+        for pool in self.pools() {
+            names.extend(
+                sqlx::query_scalar!(
+                    r#"SELECT name AS "name!" FROM repos WHERE name like ?1 || '/%'
+                    AND (?2 IS NULL OR pushed_at >= ?2)
+                    AND (?3 IS NULL OR name IN (SELECT name FROM repo_tags WHERE tag = ?3))
+                    AND (NOT ?4 OR is_fork = 0)
+                    AND (NOT ?5 OR source = 'owned')
+                    GROUP BY name ORDER BY length(name) ASC, name ASC LIMIT ?6"#,
+                    owner,
+                    filters.pushed_since,
+                    filters.tag,
+                    filters.hide_forks,
+                    filters.owned_only,
+                    sql_limit
+                )
+                .fetch_all(pool)
+                .await?,
+            );
+        }
+
+        Ok(merge_by_name(names, String::as_str, limit))
+    }
+
+    /// Tag the given repository
+    pub async fn add_tag(&self, repo: &str, tag: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO repo_tags(name, tag) VALUES (?, ?)",
+            repo,
+            tag
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Increment the number of times the item with the given uid was selected
+    pub async fn record_selection(&self, uid: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO selections(uid, count) VALUES (?, 1)
+            ON CONFLICT(uid) DO UPDATE SET count = count + 1",
+            uid
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// returns the number of times each recorded item was selected, keyed by uid
+    pub async fn selection_counts(&self) -> anyhow::Result<HashMap<String, i64>> {
+        let recs = sqlx::query!("SELECT uid, count FROM selections")
             .fetch_all(&self.pool)
             .await?;
+        Ok(recs.into_iter().map(|rec| (rec.uid, rec.count)).collect())
+    }
 
-        Ok(recs.into_iter().map(|repo| GHApiRepoSearchItem {
-            full_name: repo.name,
-        }))
+    /// Remove a tag from the given repository, returns false if the repository was not tagged
+    pub async fn remove_tag(&self, repo: &str, tag: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM repo_tags WHERE name = ? AND tag = ?",
+            repo,
+            tag
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
     }
 
     /// Search crates matching the given query string
@@ -58,49 +696,326 @@ impl DBClient {
         filter: &str,
     ) -> anyhow::Result<impl Iterator<Item = CrateSearchItem>> {
         log::debug!("search crates matching {filter}");
-        let filter = format!("%{}%", filter);
-        let recs = sqlx::query!("SELECT name FROM crates WHERE name like ? LIMIT 5", filter)
-            .fetch_all(&self.pool)
-            .await?;
+        let query = normalize(filter);
+        let words = query.split_whitespace().collect::<Vec<_>>();
+        let filter = format!("%{query}%");
+        // the words of the query are matched in order in the descriptions, e.g `async runtime`
+        let description_filter = format!("%{}%", words.join("%"));
+        let mut recs = Vec::new();
+        for pool in self.pools() {
+            recs.extend(
+                sqlx::query!(
+                    r#"SELECT name AS "name!", description, downloads, max_version, max_stable_version,
+                newest_version, repository, documentation
+            FROM crates WHERE name like ?1 OR description like ?2
+            ORDER BY name like ?1 DESC, length(name) ASC, name ASC LIMIT 5"#,
+                    filter,
+                    description_filter
+                )
+                .fetch_all(pool)
+                .await?,
+            );
+        }
 
-        Ok(recs
-            .into_iter()
-            .map(|repo| CrateSearchItem { name: repo.name }))
+        // the metadata is only known for the crates refreshed by the sync
+        let crates = recs.into_iter().map(|rec| CrateSearchItem {
+            matched_description: rec
+                .description
+                .as_deref()
+                .filter(|_| !rec.name.to_lowercase().contains(&query.to_lowercase()))
+                .and_then(|description| description_snippet(description, &words)),
+            name: rec.name,
+            description: rec.description,
+            downloads: rec
+                .downloads
+                .and_then(|downloads| u64::try_from(downloads).ok()),
+            max_version: rec.max_version,
+            max_stable_version: rec.max_stable_version,
+            newest_version: rec.newest_version,
+            repository: rec.repository,
+            documentation: rec.documentation,
+            ..Default::default()
+        });
+        Ok(merge_by_name(crates, |item| &item.name, SEARCH_LIMIT).into_iter())
+    }
+
+    /// Save the passed crates, replacing the metadata of the ones already cached
+    pub async fn save_crates(&self, crates: &[CrateSearchItem]) -> anyhow::Result<()> {
+        if crates.is_empty() {
+            return Ok(());
+        }
+
+        if self.dry_run {
+            log::info!("Dry run: would upsert {} crates", crates.len());
+            return Ok(());
+        }
+
+        log::info!("Upsert {} crates", crates.len());
+        let mut query_builder: QueryBuilder<sqlx::Sqlite> = QueryBuilder::new(
+            "INSERT INTO crates(name, description, downloads, max_version, max_stable_version, \
+            newest_version, repository, documentation) ",
+        );
+
+        query_builder.push_values(crates.iter(), |mut b, item| {
+            b.push_bind(normalize(&item.name))
+                .push_bind(&item.description)
+                .push_bind(
+                    item.downloads
+                        .map(|downloads| i64::try_from(downloads).unwrap_or(i64::MAX)),
+                )
+                .push_bind(&item.max_version)
+                .push_bind(&item.max_stable_version)
+                .push_bind(&item.newest_version)
+                .push_bind(&item.repository)
+                .push_bind(&item.documentation);
+        });
+        query_builder.push(
+            " ON CONFLICT(name) DO UPDATE SET description = excluded.description, \
+            downloads = excluded.downloads, max_version = excluded.max_version, \
+            max_stable_version = excluded.max_stable_version, \
+            newest_version = excluded.newest_version, repository = excluded.repository, \
+            documentation = excluded.documentation",
+        );
+
+        query_builder.build().execute(&self.pool).await?;
+        Ok(())
     }
 
     /// Save the passed repositories
-    async fn save_repositories(&self, repos: &[String]) -> anyhow::Result<()> {
+    async fn save_repositories(&self, repos: &[GHApiRepoSearchItem]) -> anyhow::Result<()> {
         if repos.is_empty() {
             return Ok(());
         }
 
-        log::info!("Insert batch starting with {}", repos[0]);
-        let mut conn = self.pool.acquire().await?;
-        let mut query_builder: QueryBuilder<sqlx::Sqlite> =
-            QueryBuilder::new("INSERT OR REPLACE INTO repos(name) ");
+        if self.dry_run {
+            log::info!(
+                "Dry run: would insert {} repos starting with {}",
+                repos.len(),
+                repos[0].full_name
+            );
+            return Ok(());
+        }
+
+        log::info!("Insert batch starting with {}", repos[0].full_name);
+        let mut tx = self.pool.begin().await?;
+        let mut query_builder: QueryBuilder<sqlx::Sqlite> = QueryBuilder::new(
+            "INSERT OR REPLACE INTO repos(name, pushed_at, is_fork, default_branch, source) ",
+        );
 
         query_builder.push_values(repos.iter(), |mut b, repo| {
-            b.push_bind(repo);
+            b.push_bind(normalize(&repo.full_name))
+                .push_bind(&repo.pushed_at)
+                .push_bind(repo.fork)
+                .push_bind(&repo.default_branch)
+                .push_bind(repo.source.map(RepoSource::as_str));
         });
 
         let query = query_builder.build();
+        query.execute(&mut tx).await?;
+
+        // replace the topics of the saved repositories
+        let mut query_builder: QueryBuilder<sqlx::Sqlite> =
+            QueryBuilder::new("DELETE FROM repo_topics WHERE name IN (");
+        let mut separated = query_builder.separated(", ");
+        for repo in repos {
+            separated.push_bind(normalize(&repo.full_name));
+        }
+        separated.push_unseparated(")");
+        query_builder.build().execute(&mut tx).await?;
+
+        let topics = repos
+            .iter()
+            .flat_map(|repo| repo.topics.iter().map(move |topic| (repo, topic)))
+            .collect::<Vec<_>>();
+        if !topics.is_empty() {
+            let mut query_builder: QueryBuilder<sqlx::Sqlite> =
+                QueryBuilder::new("INSERT OR IGNORE INTO repo_topics(name, topic) ");
+            query_builder.push_values(topics, |mut b, (repo, topic)| {
+                b.push_bind(normalize(&repo.full_name)).push_bind(topic);
+            });
+            query_builder.build().execute(&mut tx).await?;
+        }
 
-        query.execute(&mut conn).await?;
+        tx.commit().await?;
         Ok(())
     }
 
-    /// save all repositories from the passed stream
+    /// insert the given names into the table, without their details, keeping the rows that are
+    /// already cached, and returns the number of inserted rows
+    pub async fn seed_names(&self, table: NamesTable, names: &[String]) -> anyhow::Result<u64> {
+        if names.is_empty() {
+            return Ok(0);
+        }
+
+        if self.dry_run {
+            log::info!("Dry run: would seed {} names into {table:?}", names.len());
+            return Ok(0);
+        }
+
+        log::info!("Seed {} names into {table:?}", names.len());
+        let mut tx = self.pool.begin().await?;
+        let mut inserted = 0;
+        for batch in names.chunks(SEED_BATCH_SIZE) {
+            let mut query_builder: QueryBuilder<sqlx::Sqlite> = QueryBuilder::new(match table {
+                NamesTable::Repos => "INSERT OR IGNORE INTO repos(name) ",
+                NamesTable::Crates => "INSERT OR IGNORE INTO crates(name) ",
+            });
+            query_builder.push_values(batch, |mut b, name| {
+                b.push_bind(normalize(name));
+            });
+            inserted += query_builder
+                .build()
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+        }
+
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
+    /// write the committed transactions from the write-ahead log to the database file, so that
+    /// they are kept in the file itself once the process exits
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+
+        sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// save all repositories from the passed stream, flushed once the stream completes, e.g
+    /// when the sync is stopped by a signal
     pub fn save_all_repositories<'a>(
         &'a self,
-        mut src: impl Stream<Item = anyhow::Result<Vec<String>>> + std::marker::Unpin + 'a,
+        mut src: impl Stream<Item = anyhow::Result<Vec<GHApiRepoSearchItem>>> + std::marker::Unpin + 'a,
     ) -> impl Stream<Item = anyhow::Result<DBUpdateEvent>> + 'a {
         async_stream::try_stream!({
             while let Some(repos) = src.try_next().await? {
                 self.save_repositories(&repos)
                     .await
                     .context("failed to save repositories")?;
-                yield DBUpdateEvent {};
+                yield DBUpdateEvent { repos: repos.len() };
             }
+            self.flush()
+                .await
+                .context("failed to flush the saved repositories")?;
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "café" with a composed é, and with an e followed by a combining acute accent
+    const COMPOSED: &str = "owner/caf\u{e9}";
+    const DECOMPOSED: &str = "owner/cafe\u{301}";
+
+    async fn db_with_repo(full_name: &str) -> DBClient {
+        let db = DBClient::create_in_memory().await.unwrap();
+        let repo = GHApiRepoSearchItem {
+            full_name: full_name.to_string(),
+            ..Default::default()
+        };
+        db.save_repositories(&[repo]).await.unwrap();
+        db
+    }
+
+    async fn search(db: &DBClient, filter: &str) -> Vec<String> {
+        db.search_repositories(filter, &RepoFilters::default())
+            .await
+            .unwrap()
+            .map(|repo| repo.full_name)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn in_memory_databases_are_migrated_and_isolated() {
+        let db = db_with_repo(COMPOSED).await;
+        assert_eq!(
+            db.schema_version().await,
+            MIGRATOR.iter().map(|m| m.version).max()
+        );
+
+        let other = DBClient::create_in_memory().await.unwrap();
+        assert!(search(&other, "caf").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn repos_cached_by_several_databases_are_counted_once() {
+        let mut db = db_with_repo("tokio-rs/tokio").await;
+        let extra = db_with_repo("tokio-rs/tokio").await;
+        db.extra_pools.push(extra.pool.clone());
+
+        let count = db.count_repos("tokio", &RepoFilters::default()).await;
+        assert_eq!(count.unwrap(), 1);
+        assert_eq!(search(&db, "tokio").await, ["tokio-rs/tokio"]);
+    }
+
+    #[test]
+    fn merge_by_name_drops_duplicates_and_sorts_by_length_then_name() {
+        let first = ["tokio-rs/tokio", "b/tokio", "a/tokio", "a/tokio-util"];
+        let second = ["a/tokio", "c/tokio"];
+        let merged = merge_by_name(first.into_iter().chain(second), |name| name, usize::MAX);
+        assert_eq!(
+            merged,
+            [
+                "a/tokio",
+                "b/tokio",
+                "c/tokio",
+                "a/tokio-util",
+                "tokio-rs/tokio"
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_by_name_order_does_not_depend_on_the_databases_order() {
+        let first = ["b/rx", "a/rx", "a/rx-extra"];
+        let second = ["c/rx", "a/rx"];
+        let merged = merge_by_name(first.into_iter().chain(second), |name| name, 3);
+        let reversed = merge_by_name(second.into_iter().chain(first), |name| name, 3);
+        assert_eq!(merged, ["a/rx", "b/rx", "c/rx"]);
+        assert_eq!(merged, reversed);
+    }
+
+    #[test]
+    fn normalize_composes_the_decomposed_forms() {
+        assert_eq!(normalize(DECOMPOSED), COMPOSED);
+        assert_eq!(normalize(COMPOSED), COMPOSED);
+    }
+
+    #[tokio::test]
+    async fn decomposed_filter_matches_composed_name() {
+        let db = db_with_repo(COMPOSED).await;
+        assert_eq!(search(&db, "cafe\u{301}").await, [COMPOSED]);
+    }
+
+    #[tokio::test]
+    async fn composed_filter_matches_decomposed_name() {
+        let db = db_with_repo(DECOMPOSED).await;
+        assert_eq!(search(&db, "caf\u{e9}").await, [COMPOSED]);
+    }
+
+    #[test]
+    fn snippet_starts_before_the_matched_words() {
+        let description = format!("{}an async runtime for Rust", "x".repeat(30));
+        assert_eq!(
+            description_snippet(&description, &["async", "runtime"]).as_deref(),
+            Some("…xxxxxxxxxxxxxxxxxan async runtime for Rust")
+        );
+    }
+
+    #[test]
+    fn snippet_of_lowercase_with_other_char_boundaries() {
+        // `İ` gets longer and `ẞ` shorter in lowercase, keeping the length but not the boundaries
+        assert_eq!(
+            description_snippet("\u{130}a\u{1e9e}", &["\u{1e9e}"]).as_deref(),
+            Some("\u{130}a\u{1e9e}")
+        );
+    }
+}
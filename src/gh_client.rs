@@ -1,12 +1,23 @@
 //! Github client used to query Github api
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
+use crate::clock::{Clock, SystemClock};
+use crate::config::{GhAlfredConfig, GhField, GithubAppConfig, RateLimitStrategy};
 use crate::gh_client::repo_view::RepoViewRateLimit;
+use crate::http_client::{ApiError, HttpClient, HttpResponse};
 use anyhow::Context;
-use chrono::Utc;
-use graphql_client::{reqwest::post_graphql, GraphQLQuery};
+use graphql_client::GraphQLQuery;
 use reqwest::header::{self, HeaderMap, HeaderValue};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
 use tokio_stream::Stream;
 
 /// Paged GraphQLQuery to fetch all repositories associated with the logged-in user
@@ -18,63 +29,584 @@ use tokio_stream::Stream;
 )]
 struct RepoView;
 
+/// Query to fetch the README of a repository, using the GraphQLQuery stored in readme.graphql
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "./schema.graphql",
+    query_path = "./readme.graphql",
+    response_derives = "Debug"
+)]
+struct RepoReadme;
+
+/// Query to fetch the login of the authenticated user, using the GraphQLQuery stored in
+/// viewer.graphql
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "./schema.graphql",
+    query_path = "./viewer.graphql",
+    response_derives = "Debug"
+)]
+struct Viewer;
+
+/// number of consecutive empty pages after which we stop streaming the repositories
+const MAX_EMPTY_PAGES: usize = 2;
+
+/// number of languages shown for an enriched repository
+const MAX_LANGUAGES: usize = 3;
+
+/// how long a fetched README is reused before being fetched again
+const README_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
 /// DateTime type alias referenced by the graphql macro when parsing the GH graphql schema
 type DateTime = String;
 
 /// Utility class to read from GH api
 #[derive(Clone)]
 pub struct GHClient {
-    client: reqwest::Client,
+    /// how the API calls are authorized
+    auth: Arc<GHAuth>,
+    /// number of API calls made with this client
+    api_calls: Arc<AtomicU64>,
+    /// number of times we had to wait for the rate limit to reset
+    rate_limit_waits: Arc<AtomicU64>,
+    /// how the paged queries are paced against the rate limit
+    rate_limit_strategy: RateLimitStrategy,
+    /// maximum number of pages fetched when streaming the repositories
+    max_pages: usize,
+    /// timeout of the calls of the searches
+    search_timeout: Duration,
+    /// timeout of each page fetched when streaming the repositories
+    sync_timeout: Duration,
+    /// the optional details of the repositories shown, the only ones fetched by the enrichment
+    fields: Arc<[GhField]>,
+}
+
+/// The authorization used for the API calls
+enum GHAuth {
+    /// a static personal access token
+    Token(HttpClient),
+    /// a Github App installation, whose short-lived token is refreshed when near expiry
+    App {
+        app: GithubApp,
+        /// client authorized with the current installation token, and its expiry
+        client: Mutex<Option<(HttpClient, chrono::DateTime<chrono::Utc>)>>,
+    },
+}
+
+/// installation tokens expiring within this number of minutes are refreshed before the next call
+const TOKEN_REFRESH_MARGIN_MINUTES: i64 = 5;
+
+/// Claims of the JWT authenticating as a Github App
+#[derive(Serialize)]
+struct GithubAppClaims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+/// An installation of the Github App, returned by the installations API
+#[derive(Deserialize)]
+struct GithubAppInstallation {
+    id: u64,
+}
+
+/// A short-lived installation token, returned by the access tokens API
+#[derive(Deserialize)]
+struct InstallationToken {
+    token: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A Github App, able to mint installation tokens
+/// see [API doc](https://docs.github.com/en/apps/creating-github-apps/authenticating-with-a-github-app/authenticating-as-a-github-app-installation)
+struct GithubApp {
+    app_id: u64,
+    installation_id: Option<u64>,
+    key: jsonwebtoken::EncodingKey,
+    /// whether the clients negotiate compressed responses
+    gzip: bool,
+}
+
+impl GithubApp {
+    /// load the app private key from the configured path
+    fn load(config: &GithubAppConfig, gzip: bool) -> anyhow::Result<Self> {
+        let pem = std::fs::read(&config.private_key_path).with_context(|| {
+            format!(
+                "failed to read the Github App private key {}",
+                config.private_key_path.display()
+            )
+        })?;
+
+        Ok(Self {
+            app_id: config.app_id,
+            installation_id: config.installation_id,
+            key: jsonwebtoken::EncodingKey::from_rsa_pem(&pem)?,
+            gzip,
+        })
+    }
+
+    /// returns a JWT authenticating as the app, valid for a few minutes
+    fn jwt(&self) -> anyhow::Result<String> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = GithubAppClaims {
+            // backdated to allow for clock drift
+            iat: now - 60,
+            exp: now + 9 * 60,
+            iss: self.app_id.to_string(),
+        };
+
+        let header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+        Ok(jsonwebtoken::encode(&header, &claims, &self.key)?)
+    }
+
+    /// mint a new installation token, for the configured installation or the first one of the app
+    async fn installation_token(&self) -> anyhow::Result<InstallationToken> {
+        log::info!("minting a Github App installation token");
+        let client = build_client(&self.jwt()?, self.gzip)?;
+
+        let installation_id = match self.installation_id {
+            Some(id) => id,
+            None => {
+                let response = client
+                    .get("https://api.github.com/app/installations")
+                    .send()
+                    .await?;
+
+                let response = ApiError::check(response, "fetch app installations").await?;
+
+                response
+                    .json::<Vec<GithubAppInstallation>>()
+                    .await?
+                    .first()
+                    .map(|installation| installation.id)
+                    .ok_or_else(|| anyhow::format_err!("the Github App is not installed"))?
+            }
+        };
+
+        let response = client
+            .post(format!(
+                "https://api.github.com/app/installations/{installation_id}/access_tokens"
+            ))
+            .send()
+            .await?;
+
+        let response = ApiError::check(response, "create installation token").await?;
+
+        Ok(response.json::<InstallationToken>().await?)
+    }
+}
+
+/// build a http client authorizing its calls with the given bearer token
+/// Compressed (gzip or deflate) responses are transparently decompressed when `gzip` is set,
+/// otherwise they're not negotiated, i.e no `Accept-Encoding` header is sent
+fn build_client(token: &str, gzip: bool) -> anyhow::Result<HttpClient> {
+    let mut authorization = HeaderValue::from_str(&format!("Bearer {}", token))?;
+    authorization.set_sensitive(true);
+
+    let default_headers = HeaderMap::from_iter([
+        (header::AUTHORIZATION, authorization),
+        (
+            header::ACCEPT,
+            HeaderValue::from_static("application/vnd.github+json"),
+        ),
+    ]);
+
+    let builder = reqwest::Client::builder()
+        .user_agent("graphql-rust/0.10.0")
+        .gzip(gzip)
+        .deflate(gzip);
+    HttpClient::build(builder, default_headers)
 }
 
 /// Results extracted from the graphql query to cache all repositories related to the user
 #[derive(Debug)]
 struct RepoPageRead {
     /// list of repositories fetched from the API
-    repos: Vec<String>,
+    repos: Vec<GHApiRepoSearchItem>,
     /// cursor used to query the next page
     end_cursor: Option<String>,
     /// delay imposed by the rate limited GH api before we can fire the next page read
     delay: Option<Duration>,
 }
 
+/// Classic token scopes required to cache all the repositories of the logged-in user
+const REQUIRED_SCOPES: [&str; 2] = ["repo", "read:org"];
+
+/// The authenticated user returned by the Github user API
+#[derive(Deserialize)]
+struct GHApiUser {
+    login: String,
+}
+
 /// Response from the Github search API to find repositories matching our search
 #[derive(Deserialize)]
 struct GHApiRepoSearchResponse {
+    total_count: u64,
     items: Vec<GHApiRepoSearchItem>,
 }
 
+/// Response of the GraphQL query counting the good first issues, keyed by the repository aliases
+/// A repository that can't be resolved, e.g renamed, has no data
+#[derive(Deserialize)]
+struct GHApiGoodFirstIssuesResponse {
+    data: Option<HashMap<String, Option<GHApiRepoIssues>>>,
+}
+
+/// The open good first issues of a repository
+#[derive(Deserialize)]
+struct GHApiRepoIssues {
+    issues: GHApiIssuesCount,
+}
+
+/// The number of items of a GraphQL connection
+#[derive(Deserialize)]
+struct GHApiIssuesCount {
+    #[serde(rename = "totalCount")]
+    total_count: u64,
+}
+
+/// Response from the Github search API to find topics matching our search
+#[derive(Deserialize)]
+struct GHApiTopicSearchResponse {
+    items: Vec<GHApiTopic>,
+}
+
+/// A single topic returned by the Github search API
+/// see [API doc](https://docs.github.com/en/rest/search#search-topics)
+#[derive(Deserialize, Debug, Clone)]
+pub struct GHApiTopic {
+    /// the name used in the `topic:` qualifier, e.g `webassembly`
+    pub name: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub short_description: Option<String>,
+    /// whether the topic is featured or curated by Github
+    #[serde(default)]
+    pub featured: bool,
+    #[serde(default)]
+    pub curated: bool,
+}
+
+/// How a synced repository is linked to the authenticated user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoSource {
+    /// the repository is owned by the user
+    Owned,
+    /// the user is a member of the organization owning the repository, or a collaborator
+    Affiliated,
+}
+
+impl RepoSource {
+    /// returns the value stored in the `source` column of the cached repositories
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Owned => "owned",
+            Self::Affiliated => "affiliated",
+        }
+    }
+}
+
 /// A single repository item returned by the Github search API
 /// see [API doc](https://docs.github.com/en/rest/search#search-repositories)
 /// to parse more fields returned by the API
-#[derive(Deserialize)]
+#[derive(Deserialize, Default, Debug, Clone)]
 pub struct GHApiRepoSearchItem {
     pub full_name: String,
+    /// RFC 3339 timestamp of the last push to the repository
+    #[serde(default)]
+    pub pushed_at: Option<String>,
+    /// whether the repository is a fork
+    #[serde(default)]
+    pub fork: bool,
+    /// the name of the default branch, e.g `main`
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    /// details not stored in the cache, only available from the API
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub stargazers_count: Option<u64>,
+    #[serde(default)]
+    pub topics: Vec<String>,
+    /// the topic matching the search, when the name itself does not match
+    #[serde(skip)]
+    pub matched_topic: Option<String>,
+    /// local copy of the README, previewed with Quick Look
+    #[serde(skip)]
+    pub readme_path: Option<PathBuf>,
+    /// local clone of the repository, found in the configured clone roots
+    #[serde(skip)]
+    pub local_path: Option<PathBuf>,
+    /// number of open good first issues, only fetched with `--good-first-issues`
+    #[serde(skip)]
+    pub good_first_issues: Option<u64>,
+    /// how the repository is linked to the authenticated user, only known for the synced ones
+    #[serde(skip)]
+    pub source: Option<RepoSource>,
+    /// main languages of the repository, only fetched when enriching the item
+    #[serde(skip)]
+    pub languages: Option<Vec<RepoLanguage>>,
+}
+
+/// A language of a repository, and its share of the code
+#[derive(Debug, Clone)]
+pub struct RepoLanguage {
+    pub name: String,
+    /// rounded percentage of the bytes of code written in this language
+    pub percent: u64,
+}
+
+/// returns whether the given string is a valid repository full name, i.e `owner/name` where both
+/// parts only contain alphanumeric characters, '-', '_' or '.'
+pub fn is_valid_full_name(full_name: &str) -> bool {
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    full_name
+        .split_once('/')
+        .is_some_and(|(owner, name)| valid(owner) && valid(name))
+}
+
+/// error returned when the REST API budget is exhausted, from the `X-RateLimit-*` headers
+#[derive(Debug)]
+pub struct RateLimited {
+    /// when the budget is reset, if the response reported it
+    pub reset_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl RateLimited {
+    /// returns the error of the response, when the REST API budget is exhausted
+    fn of(response: &HttpResponse) -> Option<Self> {
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<i64>().ok())
+        };
+
+        // a successful response can use the last call of the budget, only failures are errors
+        if response.status().is_success() || header("x-ratelimit-remaining") != Some(0) {
+            return None;
+        }
+
+        let reset_at = header("x-ratelimit-reset")
+            .and_then(|timestamp| chrono::NaiveDateTime::from_timestamp_opt(timestamp, 0))
+            .map(|date| chrono::DateTime::from_utc(date, chrono::Utc));
+        Some(Self { reset_at })
+    }
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reset_at {
+            Some(reset_at) => write!(
+                f,
+                "Github rate limit exceeded, reset at {}",
+                reset_at.with_timezone(&chrono::Local).format("%H:%M")
+            ),
+            None => write!(f, "Github rate limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// returns the delay to wait before the next API call
+/// In burst mode we only wait, until the reset, when the remaining budget can't afford the call.
+/// In smooth mode the time left until the reset is spread between the calls we can still afford
+fn rate_limit_delay(
+    remaining: i64,
+    cost: i64,
+    reset_at: &str,
+    strategy: RateLimitStrategy,
+    clock: &impl Clock,
+) -> anyhow::Result<Option<Duration>> {
+    let exhausted = remaining - cost <= 0;
+    if !exhausted && strategy == RateLimitStrategy::Burst {
+        return Ok(None);
+    }
+
+    let reset_at = chrono::DateTime::parse_from_rfc3339(reset_at)?;
+    let until_reset = (reset_at.with_timezone(&chrono::Utc) - clock.now())
+        .to_std()
+        .ok();
+
+    if exhausted {
+        return Ok(until_reset);
+    }
+
+    // number of calls we can still afford after this one
+    let calls_left = ((remaining - cost) / cost.max(1)).max(1);
+    let calls_left = u32::try_from(calls_left).unwrap_or(u32::MAX);
+    Ok(until_reset.map(|delay| delay / calls_left))
 }
 
 impl GHClient {
-    /// Create a new Github client, using the GITHUB_API_TOKEN environment variable to authorize
-    /// API calls
+    /// Create a new Github client, authorized as the configured Github App installation, or with
+    /// the GITHUB_API_TOKEN environment variable when no app is configured
     pub async fn create() -> anyhow::Result<Self> {
-        let token = &std::env::var("GITHUB_API_TOKEN")?;
+        let config = GhAlfredConfig::load()?;
+        let gzip = config.compress_responses;
+        match &config.github_app {
+            Some(app) => {
+                let auth = GHAuth::App {
+                    app: GithubApp::load(app, gzip)?,
+                    client: Mutex::new(None),
+                };
+                Ok(Self::from_auth(Arc::new(auth), &config))
+            }
+            None => {
+                let token = std::env::var("GITHUB_API_TOKEN")?;
+                Ok(Self::from_client(build_client(&token, gzip)?, &config))
+            }
+        }
+    }
 
-        let default_headers = HeaderMap::from_iter([
-            (
-                header::AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", token))?,
-            ),
-            (
-                header::ACCEPT,
-                HeaderValue::from_static("application/vnd.github+json"),
-            ),
-        ]);
+    /// Create a new Github client calling the API with the given http client, whose default
+    /// headers authorize the calls
+    /// The forked update daemon can't reuse the client of the search process, its connections
+    /// belong to the runtime of the parent, so only the clients of one process share them
+    pub fn from_client(client: HttpClient, config: &GhAlfredConfig) -> Self {
+        Self::from_auth(Arc::new(GHAuth::Token(client)), config)
+    }
 
-        let client = reqwest::Client::builder()
-            .user_agent("graphql-rust/0.10.0")
-            .default_headers(default_headers)
-            .build()?;
+    /// returns a client sharing the authorization and the connections of this one, e.g its
+    /// multiplexed HTTP/2 connection, and its settings, with its own call counters
+    pub fn share(&self) -> Self {
+        Self {
+            auth: self.auth.clone(),
+            api_calls: Default::default(),
+            rate_limit_waits: Default::default(),
+            rate_limit_strategy: self.rate_limit_strategy,
+            max_pages: self.max_pages,
+            search_timeout: self.search_timeout,
+            sync_timeout: self.sync_timeout,
+            fields: self.fields.clone(),
+        }
+    }
 
-        Ok(Self { client })
+    /// a client with the given authorization, the configured timeouts and fields, and the default
+    /// pacing of the syncs
+    fn from_auth(auth: Arc<GHAuth>, config: &GhAlfredConfig) -> Self {
+        Self {
+            auth,
+            api_calls: Default::default(),
+            rate_limit_waits: Default::default(),
+            rate_limit_strategy: RateLimitStrategy::default(),
+            max_pages: usize::MAX,
+            search_timeout: config.gh_search_timeout(),
+            sync_timeout: config.gh_sync_timeout(),
+            fields: config.gh_fields.as_slice().into(),
+        }
+    }
+
+    /// returns the http client to use for the next call, refreshing the installation token if needed
+    async fn client(&self) -> anyhow::Result<HttpClient> {
+        let (app, current) = match self.auth.as_ref() {
+            GHAuth::Token(client) => return Ok(client.clone()),
+            GHAuth::App { app, client } => (app, client),
+        };
+
+        let mut current = current.lock().await;
+        if let Some((client, expires_at)) = current.as_ref() {
+            if *expires_at - chrono::Utc::now()
+                > chrono::Duration::minutes(TOKEN_REFRESH_MARGIN_MINUTES)
+            {
+                return Ok(client.clone());
+            }
+        }
+
+        let token = app.installation_token().await?;
+        let client = build_client(&token.token, app.gzip)?;
+        *current = Some((client.clone(), token.expires_at));
+        Ok(client)
+    }
+
+    /// returns a client pacing the paged queries with the given strategy
+    pub fn with_rate_limit_strategy(self, rate_limit_strategy: RateLimitStrategy) -> Self {
+        Self {
+            rate_limit_strategy,
+            ..self
+        }
+    }
+
+    /// returns a client fetching at most `max_pages` pages when streaming the repositories
+    pub fn with_max_pages(self, max_pages: usize) -> Self {
+        Self { max_pages, ..self }
+    }
+
+    /// check that the client is authorized, minting the installation token of the Github App
+    /// The rate limit endpoint doesn't count against the rate limit, so this also cheaply opens
+    /// the connection reused by the next calls
+    pub async fn check_auth(&self) -> anyhow::Result<()> {
+        let response = self
+            .client()
+            .await?
+            .get("https://api.github.com/rate_limit")
+            .timeout(self.search_timeout)
+            .send()
+            .await?;
+
+        ApiError::check(response, "check authorization").await?;
+
+        Ok(())
+    }
+
+    /// returns the number of API calls made with this client
+    pub fn api_calls(&self) -> u64 {
+        self.api_calls.load(Ordering::Relaxed)
+    }
+
+    /// returns the number of times this client waited for the rate limit to reset
+    pub fn rate_limit_waits(&self) -> u64 {
+        self.rate_limit_waits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the scopes required to sync the repositories that the token is missing
+    /// Only classic tokens report their scopes (X-OAuth-Scopes header), fine-grained tokens are
+    /// assumed to be correctly configured
+    pub async fn missing_token_scopes(&self) -> anyhow::Result<Vec<&'static str>> {
+        if let GHAuth::App { .. } = self.auth.as_ref() {
+            log::info!("Authenticated as a Github App installation, skip scopes check");
+            return Ok(Vec::new());
+        }
+
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response = self
+            .client()
+            .await?
+            .get("https://api.github.com/user")
+            .timeout(self.search_timeout)
+            .send()
+            .await?;
+
+        let response = ApiError::check(response, "fetch authenticated user").await?;
+
+        let scopes = response
+            .headers()
+            .get("X-OAuth-Scopes")
+            .map(|scopes| scopes.to_str().unwrap_or_default().to_string());
+        let login = response.json::<GHApiUser>().await?.login;
+
+        let scopes = match scopes {
+            Some(scopes) => scopes,
+            None => {
+                log::info!("Authenticated as {login} with a fine-grained token, skip scopes check");
+                return Ok(Vec::new());
+            }
+        };
+
+        log::info!("Authenticated as {login} with scopes: {scopes}");
+        let scopes = scopes.split(',').map(str::trim).collect::<Vec<_>>();
+        Ok(REQUIRED_SCOPES
+            .into_iter()
+            .filter(|scope| !scopes.contains(scope))
+            .collect())
     }
 
     /// Search repositories matching the given query string
@@ -82,56 +614,334 @@ impl GHClient {
         &self,
         query: &str,
     ) -> anyhow::Result<Vec<GHApiRepoSearchItem>> {
+        Ok(self.search(query, 5).await?.items)
+    }
+
+    /// Count the repositories matching the given query string
+    pub async fn count_repositories(&self, query: &str) -> anyhow::Result<u64> {
+        Ok(self.search(query, 1).await?.total_count)
+    }
+
+    /// Query the search API, returning the first `per_page` repositories
+    async fn search(&self, query: &str, per_page: u8) -> anyhow::Result<GHApiRepoSearchResponse> {
         log::info!("querying api.github.com for repos matching {query}");
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
         let response = self
-            .client
+            .client()
+            .await?
             .get("https://api.github.com/search/repositories")
             .query(&[
                 ("sort", "stars"),
-                ("per_page", "5"),
+                ("per_page", &per_page.to_string()),
                 ("order", "desc"),
                 ("q", query),
             ])
+            .timeout(self.search_timeout)
             .send()
             .await?;
 
-        if !response.status().is_success() {
-            return Err(anyhow::format_err!(
-                "Failed to search repositories: {}, {}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            ));
+        if let Some(rate_limited) = RateLimited::of(&response) {
+            return Err(rate_limited.into());
         }
 
-        let items = response.json::<GHApiRepoSearchResponse>().await?.items;
+        let response = ApiError::check(response, "search repositories").await?;
 
-        Ok(items)
+        Ok(response.json::<GHApiRepoSearchResponse>().await?)
+    }
+
+    /// Search the topics matching the given query string, the featured and curated ones first
+    pub async fn search_topics(&self, query: &str) -> anyhow::Result<Vec<GHApiTopic>> {
+        log::info!("querying api.github.com for topics matching {query}");
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response = self
+            .client()
+            .await?
+            .get("https://api.github.com/search/topics")
+            .query(&[("per_page", "5"), ("q", query)])
+            // the topics are still exposed under the preview media type
+            .header(header::ACCEPT, "application/vnd.github.mercy-preview+json")
+            .timeout(self.search_timeout)
+            .send()
+            .await?;
+
+        if let Some(rate_limited) = RateLimited::of(&response) {
+            return Err(rate_limited.into());
+        }
+
+        let response = ApiError::check(response, "search topics").await?;
+
+        Ok(response.json::<GHApiTopicSearchResponse>().await?.items)
+    }
+
+    /// Count the open issues labeled as good first issues of each of the given repositories, in a
+    /// single GraphQL query aliasing the repositories as `r0`, `r1`, ...
+    /// The count of a repository that can't be resolved is None
+    pub async fn good_first_issues(&self, full_names: &[&str]) -> anyhow::Result<Vec<Option<u64>>> {
+        if full_names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        log::info!(
+            "querying api.github.com for the good first issues of {} repositories",
+            full_names.len()
+        );
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let repositories = full_names
+            .iter()
+            .enumerate()
+            .filter_map(|(index, full_name)| {
+                let (owner, name) = full_name.split_once('/')?;
+                Some(format!(
+                    r#"r{index}: repository(owner: {}, name: {}) {{
+                        issues(labels: ["good first issue"], states: OPEN) {{ totalCount }}
+                    }}"#,
+                    serde_json::to_string(owner).ok()?,
+                    serde_json::to_string(name).ok()?,
+                ))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let response = self
+            .client()
+            .await?
+            .post("https://api.github.com/graphql")
+            .json(&serde_json::json!({ "query": format!("query {{ {repositories} }}") }))
+            .timeout(self.search_timeout)
+            .send()
+            .await?;
+
+        let response = ApiError::check(response, "count good first issues").await?;
+
+        // the unresolved repositories are reported as errors, next to the data of the others
+        let mut data = response
+            .json::<GHApiGoodFirstIssuesResponse>()
+            .await?
+            .data
+            .unwrap_or_default();
+        Ok((0..full_names.len())
+            .map(|index| {
+                let repository = data.remove(&format!("r{index}")).flatten()?;
+                Some(repository.issues.total_count)
+            })
+            .collect())
+    }
+
+    /// Fetch the details of the given repository
+    pub async fn repository_details(&self, full_name: &str) -> anyhow::Result<GHApiRepoSearchItem> {
+        log::info!("querying api.github.com for repo {full_name} details");
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response = self
+            .client()
+            .await?
+            .get(format!("https://api.github.com/repos/{full_name}"))
+            .timeout(self.search_timeout)
+            .send()
+            .await?;
+
+        let response = ApiError::check(response, "fetch repository details").await?;
+
+        Ok(response.json::<GHApiRepoSearchItem>().await?)
+    }
+
+    /// Fetch the main languages of the given repository, by decreasing share of the code
+    /// see [API doc](https://docs.github.com/en/rest/repos/repos#list-repository-languages)
+    pub async fn languages(&self, full_name: &str) -> anyhow::Result<Vec<RepoLanguage>> {
+        log::info!("querying api.github.com for repo {full_name} languages");
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response = self
+            .client()
+            .await?
+            .get(format!(
+                "https://api.github.com/repos/{full_name}/languages"
+            ))
+            .timeout(self.search_timeout)
+            .send()
+            .await?;
+
+        let response = ApiError::check(response, "fetch repository languages").await?;
+
+        // bytes of code per language
+        let mut bytes = response
+            .json::<HashMap<String, u64>>()
+            .await?
+            .into_iter()
+            .collect::<Vec<_>>();
+        bytes.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+
+        let total = bytes.iter().map(|(_, count)| count).sum::<u64>().max(1);
+        Ok(bytes
+            .into_iter()
+            .take(MAX_LANGUAGES)
+            .map(|(name, count)| RepoLanguage {
+                name,
+                percent: (count * 100 + total / 2) / total,
+            })
+            .collect())
+    }
+
+    /// fetch the details the given item is missing, and its languages
+    pub async fn enrich_repository(
+        &self,
+        item: GHApiRepoSearchItem,
+    ) -> anyhow::Result<GHApiRepoSearchItem> {
+        // the cached items miss the details, only worth fetching when some of them are shown
+        let wants_details = self
+            .fields
+            .iter()
+            .any(|field| matches!(field, GhField::Stars | GhField::Description));
+        let mut item = match item.stargazers_count {
+            None if wants_details => self.repository_details(&item.full_name).await?,
+            _ => item,
+        };
+
+        if self.fields.contains(&GhField::Languages) {
+            item.languages = self
+                .languages(&item.full_name)
+                .await
+                .map_err(|err| log::warn!("failed to fetch repository languages: {err:?}"))
+                .ok();
+        }
+
+        Ok(item)
+    }
+
+    /// returns the login of the authenticated user
+    pub async fn viewer_login(&self) -> anyhow::Result<String> {
+        log::info!("querying api.github.com for the viewer login");
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response_body = self
+            .client()
+            .await?
+            .post_graphql::<Viewer>(
+                "https://api.github.com/graphql",
+                viewer::Variables,
+                self.search_timeout,
+            )
+            .await?;
+
+        let data = response_body
+            .data
+            .ok_or_else(|| anyhow::format_err!("missing viewer data from response"))?;
+        Ok(data.viewer.login)
+    }
+
+    /// returns the path of a local copy of the repository README, fetched unless cached recently
+    /// returns None if the repository has no README.md
+    pub async fn readme_file(&self, full_name: &str) -> anyhow::Result<Option<PathBuf>> {
+        let path = std::env::temp_dir()
+            .join(concat!(env!("CARGO_PKG_NAME"), "-readme"))
+            .join(format!("{}.md", full_name.replace('/', "__")));
+
+        let fresh = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age < README_CACHE_TTL);
+        if fresh {
+            return Ok(Some(path));
+        }
+
+        let (owner, name) = full_name
+            .split_once('/')
+            .ok_or_else(|| anyhow::format_err!("invalid repository name {full_name}"))?;
+
+        log::info!("querying api.github.com for repo {full_name} README");
+        let variables = repo_readme::Variables {
+            owner: owner.to_string(),
+            name: name.to_string(),
+        };
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response_body = self
+            .client()
+            .await?
+            .post_graphql::<RepoReadme>(
+                "https://api.github.com/graphql",
+                variables,
+                self.search_timeout,
+            )
+            .await?;
+
+        let object = response_body
+            .data
+            .and_then(|data| data.repository)
+            .and_then(|repository| repository.object);
+
+        let text = match object {
+            Some(repo_readme::RepoReadmeRepositoryObject::Blob(blob)) => blob.text,
+            _ => None,
+        };
+
+        match text {
+            Some(text) => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&path, text)?;
+                Ok(Some(path))
+            }
+            None => Ok(None),
+        }
     }
 
     /// fetch one page of result from the repositories graphlql query, starting after the given
     /// `after` cursor
     async fn fetch_repositories(&self, after: Option<String>) -> anyhow::Result<RepoPageRead> {
         let variables = repo_view::Variables { after };
-        let response_body =
-            post_graphql::<RepoView, _>(&self.client, "https://api.github.com/graphql", variables)
-                .await?;
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response_body = self
+            .client()
+            .await?
+            .post_graphql::<RepoView>(
+                "https://api.github.com/graphql",
+                variables,
+                self.sync_timeout,
+            )
+            .await?;
 
         let data = response_body
             .data
             .ok_or_else(|| anyhow::format_err!("Missing data"))?;
 
-        // extracts repos from response body
-        let repos = data
+        // extracts repos from response body, skipping the malformed nodes rather than failing the
+        // whole page
+        let nodes = data
             .viewer
             .repositories
             .nodes
-            .ok_or_else(|| anyhow::format_err!("missing nodes data from response"))?
+            .ok_or_else(|| anyhow::format_err!("missing nodes data from response"))?;
+        let node_count = nodes.len();
+        let viewer = &data.viewer.login;
+        let repos = nodes
             .into_iter()
-            .map(|node| {
-                node.map(|n| n.name_with_owner)
-                    .ok_or_else(|| anyhow::format_err!("missing name_with_owner field"))
+            .flatten()
+            .filter(|node| is_valid_full_name(&node.name_with_owner))
+            .map(|n| GHApiRepoSearchItem {
+                // the owner is checked first, since the name is moved into the item
+                source: Some(match n.name_with_owner.split_once('/') {
+                    Some((owner, _)) if owner == viewer => RepoSource::Owned,
+                    _ => RepoSource::Affiliated,
+                }),
+                full_name: n.name_with_owner,
+                pushed_at: n.pushed_at,
+                fork: n.is_fork,
+                default_branch: n.default_branch_ref.map(|branch| branch.name),
+                topics: n
+                    .repository_topics
+                    .nodes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flatten()
+                    .map(|node| node.topic.name)
+                    .collect(),
+                ..Default::default()
             })
-            .try_collect::<Vec<_>>()?;
+            .collect::<Vec<_>>();
+
+        let skipped = node_count - repos.len();
+        if skipped > 0 {
+            log::warn!("skipped {skipped} malformed repositories of the page");
+        }
 
         // extracts rate limit parameters
         let RepoViewRateLimit {
@@ -147,13 +957,13 @@ impl GHClient {
         let end_cursor = data.viewer.repositories.page_info.end_cursor;
 
         // calculate delay for next API call
-        let delay = if remaining - cost > 0 {
-            None
-        } else {
-            let reset_at = chrono::DateTime::parse_from_rfc3339(&reset_at)?.naive_utc();
-            let delay = reset_at - Utc::now().naive_utc();
-            delay.to_std().map(Some).unwrap_or(None)
-        };
+        let delay = rate_limit_delay(
+            remaining,
+            cost,
+            &reset_at,
+            self.rate_limit_strategy,
+            &SystemClock,
+        )?;
 
         Ok(RepoPageRead {
             repos,
@@ -163,11 +973,14 @@ impl GHClient {
     }
 
     /// Stream all repositories using the GraphQLQuery stored in query.graphql
-    pub fn stream_repositories(&self) -> impl Stream<Item = anyhow::Result<Vec<String>>> + '_ {
+    pub fn stream_repositories(
+        &self,
+    ) -> impl Stream<Item = anyhow::Result<Vec<GHApiRepoSearchItem>>> + '_ {
         log::info!("start streaming repositories");
         async_stream::try_stream!({
             let mut after = None;
-            loop {
+            let mut empty_pages = 0;
+            for page in 1.. {
                 let RepoPageRead {
                     repos,
                     end_cursor,
@@ -177,12 +990,26 @@ impl GHClient {
                     .await
                     .context("failed to fetch repository")?;
 
+                // an empty page with a cursor should not happen, stop before looping forever
+                empty_pages = if repos.is_empty() { empty_pages + 1 } else { 0 };
+                if empty_pages >= MAX_EMPTY_PAGES && end_cursor.is_some() {
+                    log::warn!(
+                        "{empty_pages} consecutive empty pages, stop streaming repositories"
+                    );
+                    break;
+                }
+
                 yield repos;
 
                 if end_cursor.is_none() {
                     break;
                 }
 
+                if page >= self.max_pages {
+                    log::warn!("reached the maximum of {page} pages, stop streaming repositories");
+                    break;
+                }
+
                 after = end_cursor;
 
                 if let Some(duration) = delay {
@@ -190,9 +1017,44 @@ impl GHClient {
                         "Rate Limit: Wait {:?} before making next GH api call",
                         duration
                     );
+                    self.rate_limit_waits.fetch_add(1, Ordering::Relaxed);
                     tokio::time::sleep(duration).await;
                 }
             }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn clock() -> FixedClock {
+        FixedClock("2026-10-14T12:00:00Z".parse().unwrap())
+    }
+
+    const RESET_AT: &str = "2026-10-14T12:10:00Z";
+
+    #[test]
+    fn burst_only_waits_once_exhausted() {
+        let delay = rate_limit_delay(100, 1, RESET_AT, RateLimitStrategy::Burst, &clock());
+        assert_eq!(delay.unwrap(), None);
+
+        let delay = rate_limit_delay(1, 1, RESET_AT, RateLimitStrategy::Burst, &clock());
+        assert_eq!(delay.unwrap(), Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn smooth_spreads_the_remaining_calls_until_the_reset() {
+        let delay = rate_limit_delay(11, 1, RESET_AT, RateLimitStrategy::Smooth, &clock());
+        assert_eq!(delay.unwrap(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn past_reset_never_waits() {
+        let reset_at = "2026-10-14T11:00:00Z";
+        let delay = rate_limit_delay(0, 1, reset_at, RateLimitStrategy::Burst, &clock());
+        assert_eq!(delay.unwrap(), None);
+    }
+}
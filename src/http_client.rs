@@ -0,0 +1,246 @@
+//! Http client shared by the API clients, logging the outbound requests with `--debug-request`
+use crate::config::GhAlfredConfig;
+use graphql_client::GraphQLQuery;
+use reqwest::{
+    header::{self, HeaderMap},
+    IntoUrl, Method,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// whether the outbound requests are logged, set by `--debug-request`
+static DEBUG_REQUESTS: AtomicBool = AtomicBool::new(false);
+
+/// the permits of the outbound requests, shared by all the clients, see `max_concurrent_requests`
+static REQUEST_PERMITS: OnceLock<Semaphore> = OnceLock::new();
+
+/// returns the permits of the outbound requests, sized from the config on first use
+fn request_permits() -> &'static Semaphore {
+    REQUEST_PERMITS.get_or_init(|| {
+        let max = GhAlfredConfig::load()
+            .map(|config| config.max_concurrent_requests)
+            .unwrap_or_else(|_| GhAlfredConfig::default().max_concurrent_requests);
+        Semaphore::new(max.max(1))
+    })
+}
+
+/// print the method, url, query parameters and headers of every outbound request to stderr
+pub fn enable_debug_requests() {
+    DEBUG_REQUESTS.store(true, Ordering::Relaxed);
+}
+
+/// error returned when an API responds with a non-success status
+#[derive(Debug)]
+pub struct ApiError {
+    /// what the request was doing, e.g `search crate`
+    action: &'static str,
+    pub status: reqwest::StatusCode,
+    body: String,
+}
+
+impl ApiError {
+    /// returns the response when its status is a success, or the error with its status and body
+    pub async fn check(response: HttpResponse, action: &'static str) -> Result<HttpResponse, Self> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        Err(Self {
+            action,
+            status: response.status(),
+            body: response.text().await.unwrap_or_default(),
+        })
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to {}: {}, {}",
+            self.action, self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// A http client, keeping its default headers so that they can be logged with the requests
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    default_headers: Arc<HeaderMap>,
+}
+
+impl HttpClient {
+    /// build the client, sending the given default headers with every request
+    pub fn build(
+        builder: reqwest::ClientBuilder,
+        default_headers: HeaderMap,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: builder.default_headers(default_headers.clone()).build()?,
+            default_headers: Arc::new(default_headers),
+        })
+    }
+
+    /// start building a GET request
+    pub fn get(&self, url: impl IntoUrl) -> HttpRequest {
+        self.request(Method::GET, url)
+    }
+
+    /// start building a POST request
+    pub fn post(&self, url: impl IntoUrl) -> HttpRequest {
+        self.request(Method::POST, url)
+    }
+
+    fn request(&self, method: Method, url: impl IntoUrl) -> HttpRequest {
+        HttpRequest {
+            client: self.client.clone(),
+            builder: self.client.request(method, url),
+            default_headers: self.default_headers.clone(),
+        }
+    }
+
+    /// post the graphql query, the same way as `graphql_client::reqwest::post_graphql`, giving up
+    /// after the timeout
+    pub async fn post_graphql<Q: GraphQLQuery>(
+        &self,
+        url: &str,
+        variables: Q::Variables,
+        timeout: Duration,
+    ) -> reqwest::Result<graphql_client::Response<Q::ResponseData>> {
+        self.post(url)
+            .json(&Q::build_query(variables))
+            .timeout(timeout)
+            .send()
+            .await?
+            .json()
+            .await
+    }
+}
+
+/// A request being built, logged when it's sent
+pub struct HttpRequest {
+    client: reqwest::Client,
+    builder: reqwest::RequestBuilder,
+    default_headers: Arc<HeaderMap>,
+}
+
+impl HttpRequest {
+    /// append the given query parameters to the url
+    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> Self {
+        Self {
+            builder: self.builder.query(query),
+            ..self
+        }
+    }
+
+    /// add the given header to the request, replacing the default one
+    pub fn header(self, name: header::HeaderName, value: &'static str) -> Self {
+        Self {
+            builder: self.builder.header(name, value),
+            ..self
+        }
+    }
+
+    /// send the given value as the JSON body of the request
+    pub fn json<T: Serialize + ?Sized>(self, json: &T) -> Self {
+        Self {
+            builder: self.builder.json(json),
+            ..self
+        }
+    }
+
+    /// give up on the request after the timeout, from the start of the connection to the end of
+    /// the response body
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            builder: self.builder.timeout(timeout),
+            ..self
+        }
+    }
+
+    /// send the request, logging it first when `--debug-request` is set
+    /// The request waits for one of the `max_concurrent_requests` permits, held by the response
+    /// until its body is read
+    pub async fn send(self) -> reqwest::Result<HttpResponse> {
+        // the semaphore is never closed
+        let permit = request_permits().acquire().await.ok();
+        let response = if DEBUG_REQUESTS.load(Ordering::Relaxed) {
+            let request = self.builder.build()?;
+            log_request(&request, &self.default_headers);
+            self.client.execute(request).await?
+        } else {
+            self.builder.send().await?
+        };
+
+        Ok(HttpResponse {
+            response,
+            _permit: permit,
+        })
+    }
+}
+
+/// A response whose body is still to be read, holding the permit of its request until then
+pub struct HttpResponse {
+    response: reqwest::Response,
+    _permit: Option<SemaphorePermit<'static>>,
+}
+
+impl HttpResponse {
+    /// the status of the response
+    pub fn status(&self) -> reqwest::StatusCode {
+        self.response.status()
+    }
+
+    /// the headers of the response
+    pub fn headers(&self) -> &HeaderMap {
+        self.response.headers()
+    }
+
+    /// read the body as JSON, releasing the permit once it's read
+    pub async fn json<T: DeserializeOwned>(self) -> reqwest::Result<T> {
+        self.response.json().await
+    }
+
+    /// read the body as text, releasing the permit once it's read
+    pub async fn text(self) -> reqwest::Result<String> {
+        self.response.text().await
+    }
+}
+
+/// print the method, url, query parameters and headers of the request, masking the credentials
+fn log_request(request: &reqwest::Request, default_headers: &HeaderMap) {
+    let url = request.url();
+    eprintln!(
+        "> {} {}://{}{}",
+        request.method(),
+        url.scheme(),
+        url.host_str().unwrap_or_default(),
+        url.path()
+    );
+    for (name, value) in url.query_pairs() {
+        eprintln!(">   ?{name}={value}");
+    }
+
+    // the headers of the request take precedence over the default ones
+    let defaults = default_headers
+        .iter()
+        .filter(|(name, _)| !request.headers().contains_key(*name));
+    for (name, value) in request.headers().iter().chain(defaults) {
+        let value = if value.is_sensitive() || name == header::AUTHORIZATION {
+            "***".into()
+        } else {
+            String::from_utf8_lossy(value.as_bytes())
+        };
+        eprintln!(">   {name}: {value}");
+    }
+}
@@ -0,0 +1,19 @@
+//! Search github repositories and rust crates, from a local cache of the starred repositories and
+//! the crates.io index, or live from the APIs
+//!
+//! The `alfred-search-rs` binary is a command line wrapper of this library, so that other tools can
+//! run the same searches, e.g `alfred_search_rs::search_repositories("tokio").await`
+pub mod alfred;
+pub mod clock;
+pub mod config;
+pub mod crate_client;
+pub mod db_client;
+pub mod gh_client;
+pub mod http_client;
+pub mod metrics;
+pub mod pending_search;
+pub mod ranking;
+pub mod search;
+pub mod spawn_daemon;
+
+pub use search::{search_crates, search_repositories};
@@ -1,19 +1,29 @@
-#![feature(iterator_try_collect)]
-mod alfred;
-mod config;
-mod crate_client;
-mod db_client;
-mod gh_client;
-mod spawn_daemon;
-use crate::crate_client::CrateClient;
-use crate::{
-    alfred::AlfredItem, db_client::DBClient, gh_client::GHClient, spawn_daemon::DaemonResult,
+use alfred_search_rs::{
+    alfred::{is_alfred, AlfredItem, AlfredResponse, RepoFormat},
+    clock, config,
+    crate_client::{self, CrateClient},
+    db_client::{self, DBClient, DBCounts, NamesTable, RepoFilters},
+    gh_client::{self, GHClient},
+    http_client, metrics, pending_search,
+    pending_search::{PendingSearch, PendingStatus, SearchDeferred},
+    search::{
+        count_crates, count_gh_repositories, crate_deps, find_crate, owner_prefix, search_crate,
+        search_gh_repositories, search_owner_repositories, search_topics, top_crates,
+        SearchOptions, RESULTS_LIMIT,
+    },
+    spawn_daemon::{self, DaemonResult},
 };
-use clap::Parser;
+use anyhow::Context;
+use clap::{builder::RangedU64ValueParser, CommandFactory, Parser};
 use futures::try_join;
-use futures::TryStreamExt;
+use futures::{future::BoxFuture, future::Shared, Future, FutureExt, StreamExt, TryStreamExt};
 use serde::Serialize;
-use spawn_daemon::spawn_daemon;
+use spawn_daemon::{spawn_daemon, UpdateLock};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 
 // Parsed command instructions from the command line
 #[derive(Parser)]
@@ -22,43 +32,448 @@ struct GhAlfredCommand {
     /// the command to execute
     #[clap(subcommand)]
     command: CliCommand,
+    /// consider the cache stale after this number of minutes, instead of the configured threshold
+    /// (`0` always triggers a background update)
+    #[clap(long, global = true)]
+    refresh_after: Option<u32>,
+    /// write the search results to this file, instead of stdout
+    #[clap(long, global = true)]
+    output_file: Option<PathBuf>,
+    /// use the tokens, databases and limits of this configured profile
+    #[clap(long, global = true)]
+    profile: Option<String>,
+    /// load the environment variables from this file, instead of the optional `.env` file
+    #[clap(long, global = true)]
+    env_file: Option<PathBuf>,
+    /// format of the search results, and of the errors when run outside of Alfred
+    #[clap(long, global = true, value_enum, default_value_t)]
+    format: OutputFormat,
+    /// indent the JSON results, the default of the debug builds, only for the json format
+    #[clap(long, global = true, conflicts_with = "compact")]
+    pretty: bool,
+    /// print the JSON results on a single line, the default of the release builds, only for the
+    /// json format
+    #[clap(long, global = true)]
+    compact: bool,
+    /// print the method, url and headers of every API request to stderr, credentials masked
+    #[clap(long, global = true)]
+    debug_request: bool,
+    /// application opening the urls of the results, e.g `Safari`, instead of the configured one
+    #[clap(long, global = true, value_name = "APP")]
+    open_in: Option<String>,
 }
 
+/// Formats of the search results
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+enum OutputFormat {
+    /// the JSON script filter format
+    #[default]
+    Json,
+    /// the legacy XML script filter format, for older Alfred workflows and integrations
+    Xml,
+    /// CSV rows of the name, url, stars or downloads, and description of the results, e.g for
+    /// spreadsheets
+    Csv,
+}
+
+impl GhAlfredCommand {
+    /// returns whether the JSON results are indented, with `--pretty`, or by default in debug
+    /// builds unless `--compact` is set
+    fn pretty(&self) -> bool {
+        self.pretty || (cfg!(debug_assertions) && !self.compact)
+    }
+
+    /// exit with a usage error when `--pretty` or `--compact` is set for another format than JSON
+    fn check_json_flags(&self) {
+        if (self.pretty || self.compact) && !matches!(self.format, OutputFormat::Json) {
+            Self::command()
+                .error(
+                    clap::ErrorKind::ArgumentConflict,
+                    "--pretty and --compact only apply to the json format",
+                )
+                .exit();
+        }
+    }
+}
+
+/// Maximum number of crates listed by top-crates
+const MAX_TOP_CRATES: u64 = 1000;
+
 /// The subcommand to execute
 #[derive(Parser, Debug)]
 enum CliCommand {
     /// Search for a github repository
-    SearchGH { filter: String },
+    SearchGH {
+        filter: String,
+        /// only return repositories pushed within this duration (e.g `30d`, `6mo`, `1y`)
+        #[clap(long, value_parser = parse_duration, conflicts_with = "names-only")]
+        since: Option<chrono::Duration>,
+        /// only return the cached repositories with this tag
+        #[clap(long, conflicts_with = "names-only")]
+        tag: Option<String>,
+        /// only return the repositories owned by the authenticated user
+        #[clap(long, conflicts_with = "names-only")]
+        mine: bool,
+        /// only return the repositories with open good first issues, showing their count
+        #[clap(long, conflicts_with_all = &["names-only", "count", "cached-only"])]
+        good_first_issues: bool,
+        /// the value passed as the arg of the results
+        #[clap(long, value_enum, default_value_t, conflicts_with_all = &["names-only", "count"])]
+        repo_format: RepoFormat,
+        #[clap(flatten)]
+        options: SearchOptions,
+    },
+    /// Search for a Github topic, completing to the repository search of the selected topic
+    SearchTopic {
+        filter: String,
+        /// the value passed as the arg of the repositories, once a topic is completed
+        #[clap(long, value_enum, default_value_t)]
+        repo_format: RepoFormat,
+        #[clap(flatten)]
+        options: SearchOptions,
+    },
     /// Search for a rust crate
-    SearchCrate { filter: String },
+    SearchCrate {
+        filter: String,
+        /// resolve the crate with this exact name, instead of searching for matching crates
+        #[clap(long, conflicts_with_all = &["cached-only", "names-only", "count"])]
+        exact: bool,
+        /// list the given number of most recent versions of the exact crate
+        #[clap(long, value_name = "N", requires = "exact")]
+        with_versions: Option<usize>,
+        #[clap(flatten)]
+        options: SearchOptions,
+    },
+    /// List the most downloaded rust crates
+    TopCrates {
+        /// the number of crates to list, each page of 100 crates being an API call
+        #[clap(value_parser = RangedU64ValueParser::<usize>::new().range(1..=MAX_TOP_CRATES))]
+        count: usize,
+    },
+    /// List the direct dependencies of a rust crate
+    CrateDeps {
+        name: String,
+        /// the version of the crate, defaults to the latest stable one
+        version: Option<String>,
+    },
     /// Update the database
     /// This is is mainly useful for testing purpose, as the update will be launched in a
     /// background daeamon process on regular basis to keep the cache up to date
-    UpdateDb,
+    UpdateDb {
+        /// stream the repositories and log what would be saved, without writing to the database
+        #[clap(long)]
+        dry_run: bool,
+    },
     /// Clear the database
     ClearDb,
+    /// Record the selection of a result, to rank the most selected results first among equals
+    /// Run by the workflow actions with the `uid` variable of the selected item
+    RecordSelection { uid: String },
+    /// Import the cached rows of a database snapshot, e.g the cache of a previous install
+    Import {
+        /// path of the sqlite snapshot
+        snapshot: PathBuf,
+    },
+    /// Cache the names listed in a file, one per line, e.g a curated list of repositories, so
+    /// that they are searchable without Github access
+    /// Blank lines and lines starting with `#` are skipped
+    Seed {
+        /// path of the names file
+        path: PathBuf,
+        /// the table of the names, `owner/name` repositories or crates
+        #[clap(long, value_enum)]
+        table: NamesTable,
+    },
+    /// Print the database sync metrics, using the Prometheus text format
+    Metrics,
+    /// Keep running and update the database periodically
+    /// This is an alternative to the background daemon, to run as a launchd or systemd service
+    Watch {
+        /// delay between two updates (e.g `30m`, `2h`)
+        #[clap(long, value_parser = parse_interval, default_value = "30m")]
+        interval: chrono::Duration,
+    },
+    /// Organize the cached repositories with tags
+    Tag {
+        #[clap(subcommand)]
+        command: TagCommand,
+    },
+    /// Print the completion script of the given shell
+    Completions {
+        #[clap(value_parser)]
+        shell: clap_complete::Shell,
+    },
+    /// Measure the latency of the cached searches, reported to stderr
+    #[clap(hide = true)]
+    Bench {
+        filter: String,
+        /// number of timed searches
+        #[clap(default_value_t = 100)]
+        iterations: usize,
+        /// search an in-memory copy of the database
+        #[clap(long)]
+        in_memory: bool,
+    },
+}
+
+/// The tag subcommand to execute
+#[derive(Parser, Debug)]
+enum TagCommand {
+    /// Tag a repository
+    Add {
+        /// the repository, e.g `owner/name`
+        repo: String,
+        tag: String,
+    },
+    /// Remove a tag from a repository
+    Rm {
+        /// the repository, e.g `owner/name`
+        repo: String,
+        tag: String,
+    },
+}
+
+/// parse a human duration such as `30m`, `2w`, `6mo` or `1y`
+fn parse_duration(value: &str) -> Result<chrono::Duration, String> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in duration {value:?}"))?;
+    let (count, unit) = value.split_at(unit_start);
+    let count = count
+        .parse::<u64>()
+        .map_err(|_| format!("invalid count in duration {value:?}"))?;
+
+    let minutes = match unit {
+        "m" => 1,
+        "h" => 60,
+        "d" => 60 * 24,
+        "w" => 60 * 24 * 7,
+        "mo" => 60 * 24 * 30,
+        "y" => 60 * 24 * 365,
+        _ => {
+            return Err(format!(
+                "unknown unit {unit:?}, expected one of m, h, d, w, mo, y"
+            ))
+        }
+    };
+
+    // the duration is subtracted from the current date, so it has to stay within its range
+    let out_of_range = || format!("duration {value:?} is too long");
+    let seconds = count.checked_mul(minutes * 60).ok_or_else(out_of_range)?;
+    let duration = chrono::Duration::from_std(std::time::Duration::from_secs(seconds))
+        .map_err(|_| out_of_range())?;
+    chrono::Utc::now()
+        .checked_sub_signed(duration)
+        .ok_or_else(out_of_range)?;
+    Ok(duration)
+}
+
+/// parse a human duration, like `parse_duration`, rejecting the empty ones
+fn parse_interval(value: &str) -> Result<chrono::Duration, String> {
+    match parse_duration(value)? {
+        interval if interval > chrono::Duration::zero() => Ok(interval),
+        _ => Err(format!("interval {value:?} must be positive")),
+    }
 }
 
-/// exeute the update database command
-async fn update_db() -> anyhow::Result<()> {
+/// A future resolving once SIGTERM is received, cloned into every phase of the updates, so that
+/// a single signal stops the current phase and the next ones
+type Shutdown = Shared<BoxFuture<'static, ()>>;
+
+/// returns the shutdown future of the process, listening to SIGTERM from now on
+fn shutdown_on_sigterm() -> anyhow::Result<Shutdown> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    Ok(async move {
+        sigterm.recv().await;
+    }
+    .boxed()
+    .shared())
+}
+
+/// exeute the update database command, stopping early when the shutdown future resolves
+async fn update_db(dry_run: bool, gh: GHClient, shutdown: Shutdown) -> anyhow::Result<()> {
     log::info!("Update DB");
+    let start = std::time::Instant::now();
+
+    // make sure only one process updates the database at a time, a dry run never writes to it
+    let lock = match dry_run {
+        true => None,
+        false => match UpdateLock::try_acquire()? {
+            Some(lock) => Some(lock),
+            None => {
+                log::info!("Another process is already updating the DB");
+                return Ok(());
+            }
+        },
+    };
 
-    // get a Github and DB client
-    let (gh, db) = try_join!(GHClient::create(), DBClient::create())?;
+    // get a DB client, and configure the Github client for the sync
+    // a dry run doesn't even open the database, since opening it applies the pending migrations
+    let config = config::GhAlfredConfig::load()?;
+    let db = match dry_run {
+        true => DBClient::create_in_memory().await?.dry_run(),
+        false => DBClient::create_updating(lock.as_ref()).await?,
+    };
+    let gh = gh
+        .with_rate_limit_strategy(config.rate_limit_strategy)
+        .with_max_pages(config.sync_max_pages);
 
-    // stream repositories
-    let repositories = gh.stream_repositories();
-    tokio::pin!(repositories);
+    // a token without the required scopes silently returns no repositories, warn about it
+    let missing_scopes = tokio::select! {
+        missing_scopes = gh.missing_token_scopes() => missing_scopes,
+        _ = shutdown.clone() => {
+            log::info!("Received SIGTERM, stop the update before syncing");
+            return Ok(());
+        }
+    };
+    match missing_scopes {
+        Ok(missing_scopes) if !missing_scopes.is_empty() => log::warn!(
+            "GITHUB_API_TOKEN is missing the scopes {} required to cache all repositories",
+            missing_scopes.join(", ")
+        ),
+        Ok(_) => {}
+        Err(err) => log::warn!("failed to check the GITHUB_API_TOKEN scopes: {err:?}"),
+    }
+
+    // stream repositories, fetching the next pages while the current one is being saved
+    // SIGTERM stops the fetch, but the pages already fetched are still saved and flushed
+    let (pages_tx, pages_rx) = mpsc::channel(config.sync_pages_in_flight.max(1));
+    let gh = &gh;
+    let sigterm = shutdown.clone();
+    let fetch = async move {
+        let repositories = gh.stream_repositories();
+        tokio::pin!(repositories);
+        loop {
+            let page = tokio::select! {
+                page = repositories.next() => page,
+                _ = sigterm.clone() => return Ok::<_, anyhow::Error>(true),
+            };
+            let Some(page) = page else { break };
+            if pages_tx.send(page).await.is_err() {
+                break;
+            }
+        }
+        Ok(false)
+    };
 
     // pipe stream to save repositories into the db
-    let inserts = db.save_all_repositories(repositories);
-    tokio::pin!(inserts);
+    let save = async {
+        let inserts = db.save_all_repositories(ReceiverStream::new(pages_rx));
+        tokio::pin!(inserts);
+
+        // consume the pipe
+        let mut repos = 0;
+        while let Some(event) = inserts.try_next().await? {
+            log::info!("Update available");
+            repos += event.repos;
+        }
+        Ok::<_, anyhow::Error>(repos)
+    };
+
+    let (interrupted, repos) = try_join!(fetch, save)?;
+    if interrupted {
+        log::info!("Received SIGTERM, stop the update after saving {repos} repos");
+        return Ok(());
+    }
+
+    if repos == 0 {
+        log::warn!("No repositories returned by Github, check the GITHUB_API_TOKEN permissions");
+    }
+
+    // refresh the metadata of the recently updated and popular crates, without a full pull
+    let crates = async { CrateClient::create().await?.summary_crates().await };
+    let crates = tokio::select! {
+        crates = crates => crates,
+        _ = shutdown.clone() => {
+            log::info!("Received SIGTERM, stop the update before refreshing the crates");
+            return Ok(());
+        }
+    };
+    match crates {
+        Ok(crates) => db.save_crates(&crates).await?,
+        Err(err) => log::warn!("Failed to fetch the crates summary: {err:?}"),
+    }
+
+    if dry_run {
+        log::info!("Dry run: streamed {repos} repos, database left untouched");
+        return Ok(());
+    }
+
+    // record the sync metrics
+    let counts = db.counts().await?;
+    metrics::SyncMetrics::load()?.record_sync(metrics::SyncReport {
+        duration: start.elapsed(),
+        repos_cached: counts.repos,
+        crates_cached: counts.crates,
+        api_calls: gh.api_calls(),
+        rate_limit_waits: gh.rate_limit_waits(),
+    })?;
+    config::GhAlfredConfig::load()?.update_last_update_success_time();
+
+    Ok(())
+}
+
+/// execute the watch command, updating the database every `interval` until we receive SIGTERM
+async fn watch(interval: chrono::Duration) -> anyhow::Result<()> {
+    let interval = interval.to_std()?;
+    let shutdown = shutdown_on_sigterm()?;
+
+    // the connections to Github are kept open between the updates
+    let gh = GHClient::create().await?;
+
+    loop {
+        // record the update, so that searches don't spawn a redundant daemon
+        config::GhAlfredConfig::load()?.update_last_update_start_time();
+
+        // an update stops on SIGTERM too, once its fetched pages are saved
+        if let Err(err) = update_db(false, gh.share(), shutdown.clone()).await {
+            log::error!("Failed to update DB: {err:?}");
+        }
 
-    // consume the pipe
-    while inserts.try_next().await?.is_some() {
-        log::info!("Update available");
+        log::info!("Next update in {interval:?}");
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.clone() => break,
+        }
     }
 
+    log::info!("Received SIGTERM, stop watching");
+    Ok(())
+}
+
+/// execute the tag command
+async fn tag(command: TagCommand) -> anyhow::Result<()> {
+    let db = DBClient::create().await?;
+    match command {
+        TagCommand::Add { repo, tag } => {
+            db.add_tag(&repo, &tag).await?;
+            log::info!("Tagged {repo} with {tag}");
+        }
+        TagCommand::Rm { repo, tag } => {
+            if db.remove_tag(&repo, &tag).await? {
+                log::info!("Removed tag {tag} from {repo}");
+            } else {
+                log::warn!("{repo} is not tagged with {tag}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// execute the completions command
+fn print_completions(shell: clap_complete::Shell) -> anyhow::Result<()> {
+    clap_complete::generate(
+        shell,
+        &mut GhAlfredCommand::command(),
+        env!("CARGO_PKG_NAME"),
+        &mut std::io::stdout(),
+    );
+    Ok(())
+}
+
+/// execute the metrics command
+fn print_metrics() -> anyhow::Result<()> {
+    print!("{}", metrics::SyncMetrics::load()?.to_prometheus());
     Ok(())
 }
 
@@ -66,96 +481,452 @@ async fn update_db() -> anyhow::Result<()> {
 async fn clear_db() -> anyhow::Result<()> {
     log::info!("Clear DB");
     let db = DBClient::create().await?;
-    config::GhAlfredConfig::load()?.reset_last_update_start_time()?;
+    config::GhAlfredConfig::load()?.reset_last_update_start_time();
     db.clear().await
 }
 
-/// Execute the search github repository command
-async fn search_gh_repositories(filter: String) -> anyhow::Result<()> {
+/// execute the import command
+async fn import(snapshot: &Path) -> anyhow::Result<()> {
+    log::info!("Import {}", snapshot.display());
     let db = DBClient::create().await?;
+    db.copy_from(&format!("sqlite://{}", snapshot.display()))
+        .await
+        .with_context(|| format!("failed to import the snapshot {}", snapshot.display()))?;
 
-    // search repositories in the db first
-    let mut repositories = db.search_repositories(&filter).await?.collect::<Vec<_>>();
+    let DBCounts { repos, crates } = db.counts().await?;
+    println!("{repos} repositories and {crates} crates cached");
+    Ok(())
+}
+
+/// execute the seed command
+async fn seed(path: &Path, table: NamesTable) -> anyhow::Result<()> {
+    log::info!("Seed {}", path.display());
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read the names file {}", path.display()))?;
 
-    // if we don't have any results we search on GH instead
-    if repositories.is_empty() {
-        let gh = GHClient::create().await?;
-        repositories = gh.search_repositories(&filter).await?;
+    let is_valid = match table {
+        NamesTable::Repos => gh_client::is_valid_full_name,
+        NamesTable::Crates => crate_client::is_valid_crate_name,
+    };
+    let mut names = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let name = line.trim();
+        if name.is_empty() || name.starts_with('#') {
+            continue;
+        }
+        if !is_valid(name) {
+            eprintln!("skip invalid name {name:?} on line {}", index + 1);
+            continue;
+        }
+        names.push(name.to_string());
     }
+    names.sort();
+    names.dedup();
 
-    let results: Vec<AlfredItem> = repositories
-        .into_iter()
-        .map(|item: gh_client::GHApiRepoSearchItem| item.into())
-        .collect::<Vec<_>>();
+    let inserted = DBClient::create().await?.seed_names(table, &names).await?;
+    println!(
+        "{inserted} names seeded, {} already cached",
+        names.len() as u64 - inserted
+    );
+    Ok(())
+}
+
+/// Number of untimed searches run before the benchmark, to warm up the pool
+const BENCH_WARMUP_ITERATIONS: usize = 10;
+
+/// Execute the bench command
+async fn bench(filter: &str, iterations: usize, in_memory: bool) -> anyhow::Result<()> {
+    let db = if in_memory {
+        let db = DBClient::create_in_memory().await?;
+        db.copy_from(&std::env::var("DATABASE_URL")?).await?;
+        db
+    } else {
+        DBClient::create().await?
+    };
 
-    print_results(&results)
+    let filters = RepoFilters::default();
+    let repos = bench_latency(iterations, || async {
+        db.search_repositories(filter, &filters).await.map(drop)
+    })
+    .await?;
+    let crates = bench_latency(iterations, || async {
+        db.search_crates(filter).await.map(drop)
+    })
+    .await?;
+
+    eprintln!("search {filter:?}, {iterations} iterations");
+    for (name, latencies) in [("repos", repos), ("crates", crates)] {
+        eprintln!(
+            "{name}: min {:?} median {:?} p95 {:?} max {:?}",
+            percentile(&latencies, 0.0),
+            percentile(&latencies, 0.5),
+            percentile(&latencies, 0.95),
+            percentile(&latencies, 1.0),
+        );
+    }
+
+    Ok(())
 }
 
-/// Execute the search crate command
-async fn search_crate(filter: String) -> anyhow::Result<()> {
-    let db = DBClient::create().await?;
+/// returns the sorted latencies of `iterations` runs of the search, after a warm up
+async fn bench_latency<F, Fut>(
+    iterations: usize,
+    search: F,
+) -> anyhow::Result<Vec<std::time::Duration>>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    for _ in 0..BENCH_WARMUP_ITERATIONS {
+        search().await?;
+    }
 
-    // search repositories in the db first
-    let mut crates = db.search_crates(&filter).await?.collect::<Vec<_>>();
+    let mut latencies = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = std::time::Instant::now();
+        search().await?;
+        latencies.push(start.elapsed());
+    }
+
+    latencies.sort();
+    Ok(latencies)
+}
 
-    // if we don't have any results we search on GH instead
-    if crates.is_empty() {
-        let client = CrateClient::create().await?;
-        crates = client.search_crate(&filter).await?;
+/// returns the given percentile (0.0 to 1.0) of the sorted latencies
+fn percentile(latencies: &[std::time::Duration], percentile: f64) -> std::time::Duration {
+    if latencies.is_empty() {
+        return std::time::Duration::ZERO;
     }
 
-    let results: Vec<AlfredItem> = crates
-        .into_iter()
-        .map(|item| item.into())
-        .collect::<Vec<_>>();
+    let index = ((latencies.len() - 1) as f64 * percentile).round() as usize;
+    latencies[index]
+}
+
+/// Number of rotated log files kept on disk
+#[cfg(not(debug_assertions))]
+const LOG_FILES_KEPT: usize = 3;
+
+/// Maximum delay, in milliseconds, before the update daemon checks again if it should update
+const SPAWN_JITTER_MS: u64 = 500;
 
-    print_results(&results)
+/// Print the number of matches of a search command
+async fn print_count(
+    count: impl Future<Output = anyhow::Result<u64>>,
+    output_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    write_output(&count.await?.to_string(), output_file)
 }
 
-/// Print the results as JSON to stdout
-fn print_results<T: Serialize>(value: &T) -> anyhow::Result<()> {
-    if cfg!(debug_assertions) {
-        println!("{}", serde_json::to_string_pretty(&value)?);
-    } else {
-        println!("{}", serde_json::to_string(&value)?);
+/// Print the names of the cached items matching the filter, skipping the rest of the search pipeline
+async fn print_names(
+    table: NamesTable,
+    filter: &str,
+    output_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let db = DBClient::create().await?;
+    let names = db.search_names(table, filter, RESULTS_LIMIT).await?;
+    write_output(&names.join("\n"), output_file)
+}
+
+/// Run a search command, and print its results
+///
+/// Searches slower than the loading delay print a placeholder, and keep running in the background.
+/// Alfred reruns the command until the background results are ready.
+/// Searches superseded by a newer query stop without printing anything
+async fn run_search(
+    search: impl Future<Output = anyhow::Result<Vec<AlfredItem>>>,
+    output_file: Option<&Path>,
+    format: OutputFormat,
+    pretty: bool,
+    open_in: Option<&str>,
+) -> anyhow::Result<()> {
+    let pending = PendingSearch::current();
+    if let Err(err) = pending.mark_latest() {
+        log::warn!("failed to record the latest search: {err:?}");
     }
+
+    let response = match pending.poll()? {
+        Some(PendingStatus::Running) => AlfredResponse::loading(),
+        Some(PendingStatus::Done(results)) => return write_output(results.trim_end(), output_file),
+        // not spawned again, the failure would most likely repeat
+        Some(PendingStatus::Failed) => AlfredResponse::new(vec![AlfredItem::info(
+            "Search failed in the background, see the logs",
+        )]),
+        None => {
+            let result = tokio::select! {
+                result = search => result,
+                // Alfred already dropped the output of the previous queries, stop their API calls
+                () = pending.superseded() => {
+                    log::info!("search superseded by a newer query, stop");
+                    return Ok(());
+                }
+            };
+
+            match result {
+                Ok(items) => AlfredResponse::new(items),
+                Err(err) if err.is::<SearchDeferred>() => {
+                    log::info!("slow search, continue in the background");
+                    pending.spawn()?;
+                    AlfredResponse::loading()
+                }
+                // the search can be retried once the budget is reset, or the registry is called
+                // again, it's not a failure
+                Err(err)
+                    if err.is::<gh_client::RateLimited>()
+                        || err.is::<crate_client::CircuitOpen>() =>
+                {
+                    AlfredResponse::new(vec![AlfredItem::info(err.to_string())])
+                }
+                // the rerun only reads the results of the background process, so its failure is
+                // reported as one
+                Err(err) if pending_search::is_background() => {
+                    log::error!("background search failed: {err:?}");
+                    AlfredResponse::new(vec![AlfredItem::info(format!("Search failed: {err}"))])
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    };
+
+    let config = config::GhAlfredConfig::load()?;
+    let response = if config.ascii_only {
+        response.ascii_only()
+    } else {
+        response
+    };
+
+    // the workflow's Open URL action targets the chosen application, the args are unchanged
+    let response = match open_in.or(config.open_in.as_deref()) {
+        Some(app) => response.with_variable("open_in", app),
+        None => response,
+    };
+    print_results(&response, output_file, format, pretty)
+}
+
+/// Print the results in the given format to stdout, or to the given output file, the JSON results
+/// being indented when `pretty` is set
+fn print_results(
+    response: &AlfredResponse,
+    output_file: Option<&Path>,
+    format: OutputFormat,
+    pretty: bool,
+) -> anyhow::Result<()> {
+    let output = match format {
+        OutputFormat::Json if pretty => serde_json::to_string_pretty(response)?,
+        OutputFormat::Json => serde_json::to_string(response)?,
+        OutputFormat::Xml => response.to_xml(),
+        OutputFormat::Csv => response.to_csv(),
+    };
+    write_output(&output, output_file)
+}
+
+/// Print the output to stdout, or write it to the given file
+/// The file is written to a temp file first, and renamed into place, so that readers never see a
+/// partial output
+fn write_output(output: &str, output_file: Option<&Path>) -> anyhow::Result<()> {
+    let path = match output_file {
+        Some(path) => path,
+        None => {
+            println!("{output}");
+            return Ok(());
+        }
+    };
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    std::fs::write(&tmp_path, format!("{output}\n"))
+        .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to move the output to {}", path.display()))?;
     Ok(())
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    // load .env file
-    dotenvy::dotenv()?;
+/// Kind of the errors reported to scripts, each with a stable exit code
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum ErrorKind {
+    /// the config file can't be loaded or saved, exits with 3
+    Config,
+    /// the cache database can't be opened or queried, exits with 4
+    Database,
+    /// an API call failed, exits with 5
+    Network,
+    /// any other failure, exits with 1
+    Other,
+}
 
+impl ErrorKind {
+    /// returns the kind of the first known error of the chain
+    fn of(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(|cause| {
+                if cause.is::<confy::ConfyError>() {
+                    Some(Self::Config)
+                } else if cause.is::<sqlx::Error>()
+                    || cause.is::<sqlx::migrate::MigrateError>()
+                    || cause.is::<db_client::CorruptDatabase>()
+                {
+                    Some(Self::Database)
+                } else if cause.is::<reqwest::Error>()
+                    || cause.is::<http_client::ApiError>()
+                    || cause.is::<gh_client::RateLimited>()
+                    || cause.is::<crate_client::CircuitOpen>()
+                {
+                    Some(Self::Network)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(Self::Other)
+    }
+
+    /// returns the exit code of the kind, 2 is left to the usage errors reported by clap
+    fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::Config => 3,
+            Self::Database => 4,
+            Self::Network => 5,
+        }
+    }
+}
+
+/// Report the error of the command, and returns its exit code
+/// Outside of Alfred, the JSON format prints `{"error": {"kind": "...", "message": "..."}}` to
+/// stdout, so that scripts can parse the failures
+fn report_error(err: &anyhow::Error, format: OutputFormat) -> ExitCode {
+    let kind = ErrorKind::of(err);
+    match format {
+        OutputFormat::Json if !is_alfred() => {
+            let error = serde_json::json!({
+                "error": { "kind": kind, "message": format!("{err:#}") }
+            });
+            println!("{error}");
+        }
+        _ => eprintln!("Error: {err:?}"),
+    }
+    ExitCode::from(kind.exit_code())
+}
+
+fn main() -> ExitCode {
     // parse the filter string from the command line
     let args = GhAlfredCommand::parse();
+    args.check_json_flags();
+    let format = args.format;
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => report_error(&err, format),
+    }
+}
+
+/// Run the parsed command
+fn run(args: GhAlfredCommand) -> anyhow::Result<()> {
+    // load the given env file, or the .env file when there is one, e.g not when run by Alfred
+    match &args.env_file {
+        Some(path) => {
+            dotenvy::from_path(path)
+                .with_context(|| format!("failed to load env file {}", path.display()))?;
+        }
+        None => {
+            dotenvy::dotenv().ok();
+        }
+    }
+
+    // apply the profile before the logger, and the runtimes, spawn their threads
+    config::select_profile(args.profile.as_deref())?;
 
     // initialize logger
     let logger = flexi_logger::Logger::try_with_env()?;
 
     #[cfg(not(debug_assertions))]
-    let logger = logger.log_to_file(flexi_logger::FileSpec::default().suppress_timestamp());
+    let logger = {
+        let config = config::GhAlfredConfig::load()?;
+        let log_dir = config
+            .log_dir
+            .or_else(|| std::env::var_os("alfred_workflow_cache").map(Into::into));
+
+        let mut file_spec = flexi_logger::FileSpec::default().suppress_timestamp();
+        if let Some(log_dir) = log_dir {
+            file_spec = file_spec.directory(log_dir);
+        }
+
+        logger.log_to_file(file_spec).rotate(
+            flexi_logger::Criterion::Size(config.log_rotate_size_mb * 1024 * 1024),
+            flexi_logger::Naming::Numbers,
+            flexi_logger::Cleanup::KeepLogFiles(LOG_FILES_KEPT),
+        )
+    };
     logger.start()?;
 
-    if !matches!(&args.command, CliCommand::UpdateDb | CliCommand::ClearDb) {
-        run_update_daemon_if_needed()?;
+    if args.debug_request {
+        http_client::enable_debug_requests();
+    }
+
+    if !matches!(
+        &args.command,
+        CliCommand::UpdateDb { .. }
+            | CliCommand::ClearDb
+            | CliCommand::Import { .. }
+            | CliCommand::Seed { .. }
+            | CliCommand::RecordSelection { .. }
+            | CliCommand::Metrics
+            | CliCommand::Watch { .. }
+            | CliCommand::Completions { .. }
+            | CliCommand::Tag { .. }
+            | CliCommand::Bench { .. }
+    ) {
+        run_update_daemon_if_needed(args.refresh_after)?;
     }
 
-    run_subcommand(args.command)?;
+    // the background search reports its results to the rerun through its stdout
+    let pretty = args.pretty();
+    let output_file = args
+        .output_file
+        .filter(|_| !pending_search::is_background());
+
+    run_subcommand(args.command, output_file, args.format, pretty, args.open_in)?;
 
     Ok(())
 }
 
+/// returns a random delay between 0 and SPAWN_JITTER_MS
+fn spawn_jitter() -> std::time::Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    // the std hasher is randomly seeded, which is random enough for a jitter
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    std::time::Duration::from_millis(random % (SPAWN_JITTER_MS + 1))
+}
+
 /// Run the update daemon if needed to warmup our local database
-fn run_update_daemon_if_needed() -> Result<(), anyhow::Error> {
+fn run_update_daemon_if_needed(refresh_after_minutes: Option<u32>) -> Result<(), anyhow::Error> {
     // read the program config
-    let mut config = config::GhAlfredConfig::load()?;
+    let config = config::GhAlfredConfig::load()?;
+
+    // a well populated cache is only updated once the soft threshold is reached,
+    // unless the threshold is explicitly overridden
+    let mut outdated = config.should_update_db(&clock::SystemClock, refresh_after_minutes);
+    if outdated && refresh_after_minutes.is_none() && config.healthy_cache_min_rows > 0 {
+        match cache_counts() {
+            Ok(counts) if config.is_cache_healthy(&clock::SystemClock, &counts) => {
+                log::info!("healthy cache {counts:?}, skip the db-update daemon");
+                outdated = false;
+            }
+            Ok(_) => {}
+            Err(err) => log::warn!("failed to count the cached rows: {err:?}"),
+        }
+    }
 
     // check weather or not we should update the db in the background
-    if config.should_update_db() {
+    if outdated {
         log::info!("config outdated, starting db-update daemon");
-        config.update_last_update_start_time()?;
         if let DaemonResult::Daemon = spawn_daemon() {
-            return run_update_daemon_fork();
+            return run_update_daemon(refresh_after_minutes);
         }
     } else {
         log::info!("config up to date, no update triggered {:?}", config);
@@ -164,6 +935,32 @@ fn run_update_daemon_if_needed() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// returns the number of rows stored in the cache
+#[tokio::main(flavor = "current_thread")]
+async fn cache_counts() -> anyhow::Result<DBCounts> {
+    let db = DBClient::create().await?;
+    let counts = db.counts().await;
+
+    // close the connections before we fork the daemon, see run_update_daemon_fork
+    db.close().await;
+    counts
+}
+
+/// Start the update in the daemon fork, unless a concurrent daemon already started it
+fn run_update_daemon(refresh_after_minutes: Option<u32>) -> Result<(), anyhow::Error> {
+    // rapid keystrokes can all see an outdated config, so the daemon waits a random delay and
+    // reads it again, to catch the update start time written by a concurrent daemon
+    std::thread::sleep(spawn_jitter());
+    let mut config = config::GhAlfredConfig::load()?;
+    if !config.should_update_db(&clock::SystemClock, refresh_after_minutes) {
+        log::info!("db-update already started by a concurrent daemon");
+        return Ok(());
+    }
+
+    config.update_last_update_start_time();
+    run_update_daemon_fork()
+}
+
 /// Execute the code that should run in the daemon fork
 ///
 /// # Note
@@ -172,16 +969,134 @@ fn run_update_daemon_if_needed() -> Result<(), anyhow::Error> {
 /// Since  daemon fork does not play well with async executors. See https://github.com/tokio-rs/tokio/issues/4301#[tokio::main]
 #[tokio::main]
 async fn run_update_daemon_fork() -> Result<(), anyhow::Error> {
-    update_db().await
+    update_db(false, GHClient::create().await?, shutdown_on_sigterm()?).await
 }
 
 /// Execute the parsed subcommand
 #[tokio::main]
-async fn run_subcommand(command: CliCommand) -> Result<(), anyhow::Error> {
+async fn run_subcommand(
+    command: CliCommand,
+    output_file: Option<PathBuf>,
+    format: OutputFormat,
+    pretty: bool,
+    open_in: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let output_file = output_file.as_deref();
+    let open_in = open_in.as_deref();
     match command {
-        CliCommand::UpdateDb => update_db().await,
+        CliCommand::UpdateDb { dry_run } => {
+            let shutdown = shutdown_on_sigterm()?;
+            update_db(dry_run, GHClient::create().await?, shutdown).await
+        }
         CliCommand::ClearDb => clear_db().await,
-        CliCommand::SearchCrate { filter } => search_crate(filter).await,
-        CliCommand::SearchGH { filter } => search_gh_repositories(filter).await,
+        CliCommand::Import { snapshot } => import(&snapshot).await,
+        CliCommand::Seed { path, table } => seed(&path, table).await,
+        CliCommand::RecordSelection { uid } => {
+            DBClient::create().await?.record_selection(&uid).await
+        }
+        CliCommand::Metrics => print_metrics(),
+        CliCommand::Watch { interval } => watch(interval).await,
+        CliCommand::Completions { shell } => print_completions(shell),
+        CliCommand::Bench {
+            filter,
+            iterations,
+            in_memory,
+        } => bench(&filter, iterations, in_memory).await,
+        CliCommand::SearchCrate {
+            filter, options, ..
+        } if options.names_only => print_names(NamesTable::Crates, &filter, output_file).await,
+        CliCommand::SearchCrate {
+            filter, options, ..
+        } if options.count => print_count(count_crates(filter, options), output_file).await,
+        CliCommand::SearchCrate {
+            filter,
+            exact: true,
+            with_versions,
+            options,
+        } => {
+            let search = find_crate(filter, with_versions, options);
+            run_search(search, output_file, format, pretty, open_in).await
+        }
+        CliCommand::SearchCrate {
+            filter, options, ..
+        } => {
+            run_search(
+                search_crate(filter, options),
+                output_file,
+                format,
+                pretty,
+                open_in,
+            )
+            .await
+        }
+        CliCommand::SearchTopic {
+            filter,
+            repo_format,
+            options,
+        } => {
+            let search = search_topics(filter, repo_format, options);
+            run_search(search, output_file, format, pretty, open_in).await
+        }
+        CliCommand::TopCrates { count } => {
+            run_search(top_crates(count), output_file, format, pretty, open_in).await
+        }
+        CliCommand::CrateDeps { name, version } => {
+            run_search(
+                crate_deps(name, version),
+                output_file,
+                format,
+                pretty,
+                open_in,
+            )
+            .await
+        }
+        CliCommand::SearchGH {
+            filter, options, ..
+        } if options.names_only => print_names(NamesTable::Repos, &filter, output_file).await,
+        CliCommand::SearchGH {
+            filter,
+            since,
+            tag,
+            mine,
+            options,
+            ..
+        } if options.count => {
+            let count = count_gh_repositories(filter, since, tag, mine, options);
+            print_count(count, output_file).await
+        }
+        CliCommand::SearchGH {
+            filter,
+            since,
+            tag,
+            mine,
+            repo_format,
+            options,
+            ..
+        } if owner_prefix(&filter).is_some() => {
+            let owner = owner_prefix(&filter).unwrap_or_default().to_string();
+            let search = search_owner_repositories(owner, since, tag, mine, repo_format, options);
+            run_search(search, output_file, format, pretty, open_in).await
+        }
+        CliCommand::SearchGH {
+            filter,
+            since,
+            tag,
+            mine,
+            good_first_issues,
+            repo_format,
+            options,
+        } => {
+            let search = search_gh_repositories(
+                filter,
+                since,
+                tag,
+                mine,
+                good_first_issues,
+                repo_format,
+                options,
+            );
+            run_search(search, output_file, format, pretty, open_in).await
+        }
+        CliCommand::Tag { command } => tag(command).await,
     }
 }
@@ -0,0 +1,107 @@
+//! Persisted counters describing the health of the database sync
+use std::fmt::Write;
+
+use confy::ConfyError;
+use serde::{Deserialize, Serialize};
+
+const METRICS_CONFIG_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "-metrics");
+
+/// Prefix of all the exported metric names
+const METRICS_PREFIX: &str = "alfred_search";
+
+/// stored metrics, updated at the end of every database sync
+#[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncMetrics {
+    /// number of completed syncs
+    pub total_syncs: u64,
+    /// duration of the last sync, in seconds
+    pub last_sync_duration_seconds: f64,
+    /// number of repositories in the cache after the last sync
+    pub repos_cached: i64,
+    /// number of crates in the cache after the last sync
+    pub crates_cached: i64,
+    /// number of API calls made by all the syncs
+    pub api_calls_total: u64,
+    /// number of times a sync had to wait for the rate limit to reset
+    pub rate_limit_waits_total: u64,
+}
+
+/// stats collected during a single sync
+pub struct SyncReport {
+    pub duration: std::time::Duration,
+    pub repos_cached: i64,
+    pub crates_cached: i64,
+    pub api_calls: u64,
+    pub rate_limit_waits: u64,
+}
+
+impl SyncMetrics {
+    /// load the metrics from the default disk location
+    pub fn load() -> Result<SyncMetrics, ConfyError> {
+        confy::load::<SyncMetrics>(METRICS_CONFIG_NAME)
+    }
+
+    /// add the stats of a completed sync and persist the metrics
+    pub fn record_sync(&mut self, report: SyncReport) -> Result<(), ConfyError> {
+        self.total_syncs += 1;
+        self.last_sync_duration_seconds = report.duration.as_secs_f64();
+        self.repos_cached = report.repos_cached;
+        self.crates_cached = report.crates_cached;
+        self.api_calls_total += report.api_calls;
+        self.rate_limit_waits_total += report.rate_limit_waits;
+        confy::store(METRICS_CONFIG_NAME, self)
+    }
+
+    /// format the metrics using the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let metrics: [(&str, &str, &str, String); 6] = [
+            (
+                "syncs_total",
+                "counter",
+                "Number of completed database syncs",
+                self.total_syncs.to_string(),
+            ),
+            (
+                "last_sync_duration_seconds",
+                "gauge",
+                "Duration of the last database sync",
+                self.last_sync_duration_seconds.to_string(),
+            ),
+            (
+                "repos_cached",
+                "gauge",
+                "Number of repositories in the cache",
+                self.repos_cached.to_string(),
+            ),
+            (
+                "crates_cached",
+                "gauge",
+                "Number of crates in the cache",
+                self.crates_cached.to_string(),
+            ),
+            (
+                "api_calls_total",
+                "counter",
+                "Number of API calls made while syncing",
+                self.api_calls_total.to_string(),
+            ),
+            (
+                "rate_limit_waits_total",
+                "counter",
+                "Number of waits imposed by the API rate limit while syncing",
+                self.rate_limit_waits_total.to_string(),
+            ),
+        ];
+
+        metrics
+            .iter()
+            .fold(String::new(), |mut out, (name, kind, help, value)| {
+                let name = format!("{METRICS_PREFIX}_{name}");
+                let _ = writeln!(out, "# HELP {name} {help}");
+                let _ = writeln!(out, "# TYPE {name} {kind}");
+                let _ = writeln!(out, "{name} {value}");
+                out
+            })
+    }
+}
@@ -0,0 +1,259 @@
+//! Slow searches deferred to a background process, and resumed through Alfred's rerun mechanism
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    hash::{Hash, Hasher},
+    io::Read,
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+/// env variable set on the background process, so that it runs the search to completion
+const BACKGROUND_ENV: &str = "ALFRED_SEARCH_RS_BACKGROUND";
+
+/// how long a live search can run before we defer it to the background
+const LOADING_DELAY: Duration = Duration::from_millis(200);
+
+/// how long the results of a background search can be picked up by a rerun
+const RESULTS_TTL: Duration = Duration::from_secs(60);
+
+/// how often a running search checks whether a newer query superseded it
+const SUPERSEDED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// error returned when a live search is deferred to the background
+#[derive(Debug)]
+pub struct SearchDeferred;
+
+impl std::fmt::Display for SearchDeferred {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "search deferred to the background")
+    }
+}
+
+impl std::error::Error for SearchDeferred {}
+
+/// returns true when running in the background process
+pub fn is_background() -> bool {
+    std::env::var_os(BACKGROUND_ENV).is_some()
+}
+
+/// run the live search, deferring it to the background if it's slower than LOADING_DELAY
+pub async fn defer_if_slow<T>(
+    search: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    if is_background() {
+        return search.await;
+    }
+
+    match tokio::time::timeout(LOADING_DELAY, search).await {
+        Ok(result) => result,
+        Err(_) => Err(SearchDeferred.into()),
+    }
+}
+
+/// State of the background search of the current command line
+pub enum PendingStatus {
+    /// the background search is still running
+    Running,
+    /// the background search completed, with the given serialized results
+    Done(String),
+    /// the background search exited without any results, e.g when it crashed
+    Failed,
+}
+
+/// returns the path of the file recording the arguments of the latest search
+fn latest_search_path() -> PathBuf {
+    std::env::temp_dir().join(format!("{}-latest-search", env!("CARGO_PKG_NAME")))
+}
+
+/// The background search of the current command line, its results are written to a temp file
+pub struct PendingSearch {
+    /// hash of the command line arguments
+    id: String,
+    path: PathBuf,
+    /// file recording the id of the latest search
+    latest_path: PathBuf,
+}
+
+impl PendingSearch {
+    /// the pending search of the current command line arguments
+    pub fn current() -> Self {
+        let mut hasher = DefaultHasher::new();
+        std::env::args_os()
+            .skip(1)
+            .for_each(|arg| arg.hash(&mut hasher));
+
+        let id = format!("{:016x}", hasher.finish());
+        let file_name = format!("{}-{id}.json", env!("CARGO_PKG_NAME"));
+        Self {
+            id,
+            path: std::env::temp_dir().join(file_name),
+            latest_path: latest_search_path(),
+        }
+    }
+
+    /// record the current command line as the latest search, superseding the searches of the
+    /// previous queries, e.g while the user is typing
+    /// Alfred reruns, and the background process, share the arguments of the current search, so
+    /// they don't supersede it
+    pub fn mark_latest(&self) -> anyhow::Result<()> {
+        if is_background() {
+            return Ok(());
+        }
+
+        // the file is renamed into place, so that readers never see a partial id
+        let path = &self.latest_path;
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&tmp_path, &self.id)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// resolves once a search of another query was started
+    pub async fn superseded(&self) {
+        loop {
+            tokio::time::sleep(SUPERSEDED_POLL_INTERVAL).await;
+            match fs::read_to_string(&self.latest_path) {
+                Ok(latest) if latest != self.id => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// returns the status of the background search, if one was started recently
+    pub fn poll(&self) -> anyhow::Result<Option<PendingStatus>> {
+        // the background process writes the results, it never reads them
+        if is_background() {
+            return Ok(None);
+        }
+
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // the background process holds the lock until it exits
+        match flock(file.as_raw_fd(), FlockArg::LockSharedNonblock) {
+            Ok(()) => {}
+            Err(Errno::EWOULDBLOCK) => return Ok(Some(PendingStatus::Running)),
+            Err(err) => return Err(err.into()),
+        }
+
+        let age = file.metadata()?.modified()?.elapsed().unwrap_or_default();
+        let mut results = String::new();
+        file.read_to_string(&mut results)?;
+        fs::remove_file(&self.path)?;
+
+        // results left over by an older search are discarded
+        if age > RESULTS_TTL {
+            return Ok(None);
+        }
+
+        // the failures are written as results, so no results means it never got to finish
+        if results.trim().is_empty() {
+            return Ok(Some(PendingStatus::Failed));
+        }
+
+        Ok(Some(PendingStatus::Done(results)))
+    }
+
+    /// run the current command line in a background process, writing its results to the temp file
+    pub fn spawn(&self) -> anyhow::Result<()> {
+        // the file is only truncated once locked, so a concurrent rerun never sees it empty
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+
+        // the lock is shared with the child through the inherited file, and released when it exits
+        flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock)?;
+        file.set_len(0)?;
+
+        Command::new(std::env::current_exe()?)
+            .args(std::env::args_os().skip(1))
+            .env(BACKGROUND_ENV, "1")
+            .stdin(Stdio::null())
+            .stdout(file)
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    /// records when the future owning it is dropped
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// a search of the given id, recording the latest search in the given directory
+    fn search(dir: &std::path::Path, id: &str) -> PendingSearch {
+        PendingSearch {
+            id: id.to_string(),
+            path: dir.join(format!("{id}.json")),
+            latest_path: dir.join("latest-search"),
+        }
+    }
+
+    #[tokio::test]
+    async fn superseded_searches_are_dropped() {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-test-{}-superseded",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        let current = search(&dir, "current");
+        current.mark_latest().unwrap();
+
+        // a network search that never completes, holding the flag
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(dropped.clone());
+        let network_search = async move {
+            let _flag = flag;
+            futures::future::pending::<()>().await
+        };
+
+        let next = search(&dir, "next");
+        let typing = async {
+            tokio::time::sleep(SUPERSEDED_POLL_INTERVAL * 2).await;
+            next.mark_latest().unwrap();
+            futures::future::pending::<()>().await
+        };
+
+        let superseded = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::select! {
+                _ = network_search => false,
+                _ = typing => false,
+                _ = current.superseded() => true,
+            }
+        })
+        .await;
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(superseded.ok(), Some(true));
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
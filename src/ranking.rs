@@ -0,0 +1,266 @@
+//! Ranking of the search results, applied uniformly to cached and live items
+use std::collections::HashMap;
+
+use crate::{
+    alfred::ItemSource,
+    config::{DedupKey, RankingWeights},
+    crate_client::CrateSearchItem,
+    gh_client::GHApiRepoSearchItem,
+};
+
+/// An item that can be ranked against a search query
+pub trait Rankable {
+    /// the name matched against the query
+    fn name(&self) -> &str;
+    /// the name identifying the item, with its owner if any
+    fn full_name(&self) -> &str {
+        self.name()
+    }
+    /// the uid of the item, keying its recorded selections
+    fn uid(&self) -> String;
+    /// the number of stars of the item, if known
+    fn stars(&self) -> Option<u64> {
+        None
+    }
+    /// the number of downloads of the item, if known
+    fn downloads(&self) -> Option<u64> {
+        None
+    }
+    /// the RFC 3339 timestamp of the last push to the item, if known
+    fn pushed_at(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Rankable for GHApiRepoSearchItem {
+    fn name(&self) -> &str {
+        // match against the repository name, without its owner
+        self.full_name
+            .split_once('/')
+            .map_or(&self.full_name, |(_, name)| name)
+    }
+
+    fn full_name(&self) -> &str {
+        &self.full_name
+    }
+
+    fn uid(&self) -> String {
+        ItemSource::Github.uid(&self.full_name)
+    }
+
+    fn stars(&self) -> Option<u64> {
+        self.stargazers_count
+    }
+
+    fn pushed_at(&self) -> Option<&str> {
+        self.pushed_at.as_deref()
+    }
+}
+
+impl Rankable for CrateSearchItem {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn uid(&self) -> String {
+        ItemSource::Crate.uid(&self.name)
+    }
+
+    fn downloads(&self) -> Option<u64> {
+        self.downloads
+    }
+}
+
+/// returns the weight of the best match of the query in the name: exact > prefix > substring
+fn match_score(name: &str, query: &str, weights: &RankingWeights) -> f64 {
+    let name = name.to_lowercase();
+    let query = query.to_lowercase();
+
+    if name == query {
+        weights.exact
+    } else if name.starts_with(&query) {
+        weights.prefix
+    } else if name.contains(&query) {
+        weights.substring
+    } else {
+        0.0
+    }
+}
+
+/// returns the count on a log scale, normalized by the biggest count of the results
+fn normalized_count(count: Option<u64>, max: u64) -> f64 {
+    match count {
+        Some(count) if max > 0 => (count as f64).ln_1p() / (max as f64).ln_1p(),
+        _ => 0.0,
+    }
+}
+
+/// returns 1 for an item pushed now, decreasing by half every 30 days
+fn recency_score(pushed_at: Option<&str>, now: chrono::DateTime<chrono::Utc>) -> f64 {
+    let pushed_at = match pushed_at.and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+    {
+        Some(pushed_at) => pushed_at,
+        None => return 0.0,
+    };
+
+    let age_days = (now - pushed_at.with_timezone(&chrono::Utc))
+        .num_days()
+        .max(0);
+    1.0 / (1.0 + age_days as f64 / 30.0)
+}
+
+/// The parameters of the ranking of the results of a search
+pub struct RankingOptions<'a> {
+    /// the query matched against the names
+    pub query: &'a str,
+    pub weights: &'a RankingWeights,
+    /// the number of items kept
+    pub limit: usize,
+    /// the number of times each item was selected, keyed by uid
+    pub selections: &'a HashMap<String, i64>,
+}
+
+/// sort the items from the best to the worst score, and keep the first `limit` ones
+pub fn rank<T: Rankable>(items: Vec<T>, options: &RankingOptions) -> Vec<T> {
+    let RankingOptions {
+        query,
+        weights,
+        limit,
+        selections,
+    } = *options;
+    let max_stars = items.iter().filter_map(T::stars).max().unwrap_or_default();
+    let max_downloads = items
+        .iter()
+        .filter_map(T::downloads)
+        .max()
+        .unwrap_or_default();
+    let selected = |item: &T| {
+        selections
+            .get(&item.uid())
+            .and_then(|count| u64::try_from(*count).ok())
+    };
+    let max_selected = items.iter().filter_map(selected).max().unwrap_or_default();
+    let now = chrono::Utc::now();
+
+    let mut scored = items
+        .into_iter()
+        .map(|item| {
+            let score = match_score(item.name(), query, weights)
+                + weights.stars * normalized_count(item.stars(), max_stars)
+                + weights.downloads * normalized_count(item.downloads(), max_downloads)
+                + weights.recency * recency_score(item.pushed_at(), now)
+                + weights.selections * normalized_count(selected(&item), max_selected);
+            (score, item)
+        })
+        .collect::<Vec<_>>();
+
+    // the sort is stable, so items with the same score keep their order
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    scored.truncate(limit);
+    scored.into_iter().map(|(_, item)| item).collect()
+}
+
+/// merge two lists of items, dropping the other items already in the first list, compared with
+/// the given key
+pub fn merge<T: Rankable>(items: Vec<T>, others: Vec<T>, dedup_key: DedupKey) -> Vec<T> {
+    let key = |item: &T| match dedup_key {
+        DedupKey::Name => Some(item.name().to_string()),
+        DedupKey::FullName => Some(item.full_name().to_string()),
+        DedupKey::None => None,
+    };
+
+    let mut items = items;
+    for item in others {
+        let duplicated = key(&item).is_some_and(|name| {
+            items
+                .iter()
+                .any(|other| key(other).as_deref() == Some(name.as_str()))
+        });
+        if !duplicated {
+            items.push(item);
+        }
+    }
+    items
+}
+
+/// returns the number of single character edits (insertion, deletion, substitution) turning
+/// one name into the other, ignoring the case
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.to_lowercase().chars().collect::<Vec<_>>();
+    let b = b.to_lowercase().chars().collect::<Vec<_>>();
+
+    // distances between the prefixes of a, and the current prefix of b
+    let mut distances = (0..=a.len()).collect::<Vec<_>>();
+    for (j, b_char) in b.iter().enumerate() {
+        let mut diagonal = distances[0];
+        distances[0] = j + 1;
+        for (i, a_char) in a.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != b_char);
+            diagonal = distances[i + 1];
+            distances[i + 1] = substitution.min(distances[i] + 1).min(distances[i + 1] + 1);
+        }
+    }
+    distances[a.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(full_name: &str, stars: u64) -> GHApiRepoSearchItem {
+        GHApiRepoSearchItem {
+            full_name: full_name.to_string(),
+            stargazers_count: Some(stars),
+            ..Default::default()
+        }
+    }
+
+    fn ranked_names(items: Vec<GHApiRepoSearchItem>, query: &str) -> Vec<String> {
+        ranked_names_with_selections(items, query, &HashMap::new())
+    }
+
+    fn ranked_names_with_selections(
+        items: Vec<GHApiRepoSearchItem>,
+        query: &str,
+        selections: &HashMap<String, i64>,
+    ) -> Vec<String> {
+        let options = RankingOptions {
+            query,
+            weights: &RankingWeights::default(),
+            limit: usize::MAX,
+            selections,
+        };
+        rank(items, &options)
+            .into_iter()
+            .map(|item| item.full_name)
+            .collect()
+    }
+
+    #[test]
+    fn prefix_match_ranks_above_popular_substring_match() {
+        let items = vec![repo("a/async-tokio", 90_000), repo("b/tokio-extra", 3)];
+        assert_eq!(
+            ranked_names(items, "tokio"),
+            ["b/tokio-extra", "a/async-tokio"]
+        );
+    }
+
+    #[test]
+    fn stars_break_the_ties_of_the_same_match() {
+        let items = vec![repo("a/tokio-small", 3), repo("b/tokio-big", 90_000)];
+        assert_eq!(
+            ranked_names(items, "tokio"),
+            ["b/tokio-big", "a/tokio-small"]
+        );
+    }
+
+    #[test]
+    fn selected_items_rank_above_popular_ones_of_the_same_match() {
+        let items = vec![repo("b/tokio-big", 90_000), repo("a/tokio-small", 3)];
+        let selections = HashMap::from([(ItemSource::Github.uid("a/tokio-small"), 5)]);
+        assert_eq!(
+            ranked_names_with_selections(items, "tokio", &selections),
+            ["a/tokio-small", "b/tokio-big"]
+        );
+    }
+}
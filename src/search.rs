@@ -0,0 +1,1242 @@
+//! The repository and crate searches, shared by the command line and the library users
+use crate::config::{self, DedupKey, SearchStrategy, Source};
+use crate::crate_client::{self, CrateClient};
+use crate::pending_search;
+use crate::ranking::{self, Rankable, RankingOptions};
+use crate::{
+    alfred::{self, AlfredItem, ItemSort, ItemSource, RepoFormat, SortField},
+    clock,
+    db_client::{DBClient, RepoFilters},
+    gh_client::{self, GHClient},
+};
+use anyhow::Context;
+use futures::{future::BoxFuture, Future, FutureExt, StreamExt};
+use std::path::PathBuf;
+use tokio::task::JoinHandle;
+
+/// Options shared by the search commands
+#[derive(clap::Args, Debug, Default)]
+pub struct SearchOptions {
+    /// only return the best result, so that the workflow can open it directly
+    #[clap(long)]
+    pub open_first: bool,
+    /// only search the cache, never query the API
+    #[clap(long, conflicts_with = "live-only")]
+    pub cached_only: bool,
+    /// skip the cache and always query the API
+    #[clap(long)]
+    pub live_only: bool,
+    /// only print the names of the matching cached items, one per line
+    #[clap(long, conflicts_with_all = &["live-only", "open-first"])]
+    pub names_only: bool,
+    /// only print the number of matches, in the first source with matches
+    #[clap(long, conflicts_with_all = &["names-only", "open-first"])]
+    pub count: bool,
+    /// only search the first source of the configured priority, never falling back to the next ones
+    #[clap(long)]
+    pub no_fallback: bool,
+    /// give up on the fallback sources after this number of milliseconds, keeping the results so far
+    #[clap(long, conflicts_with = "no-fallback")]
+    pub fallback_timeout: Option<u64>,
+    /// reorder the results on a field, one of name, stars, downloads or updated, followed by an
+    /// optional `:asc` or `:desc` direction (names default to ascending, the others to descending)
+    #[clap(long, value_parser = parse_sort, conflicts_with_all = &["names-only", "count"])]
+    pub sort: Option<ItemSort>,
+    /// describe how the results were produced: the searched sources, their number of candidates
+    /// and timings, and whether the fallback triggered
+    #[clap(long, conflicts_with_all = &["names-only", "count", "open-first"])]
+    pub explain: bool,
+    /// wait for a slow live search, instead of deferring it to the background process of the
+    /// workflow, e.g when the searches are run as a library
+    #[clap(skip)]
+    pub wait_for_live: bool,
+}
+
+impl SearchOptions {
+    /// returns the sources to search, in the configured priority order
+    pub fn sources(&self, priority: &[Source]) -> Vec<Source> {
+        self.sources_supporting(priority, |_| true)
+    }
+
+    /// returns the sources to search, in the configured priority order, among the ones supporting
+    /// the search, so that `--no-fallback` keeps the first one able to answer it
+    fn sources_supporting(
+        &self,
+        priority: &[Source],
+        supported: impl Fn(Source) -> bool,
+    ) -> Vec<Source> {
+        priority
+            .iter()
+            .copied()
+            .filter(|source| match source {
+                Source::Cache => !self.live_only,
+                Source::Live => !self.cached_only,
+            })
+            .filter(|source| supported(*source))
+            .take(if self.no_fallback { 1 } else { usize::MAX })
+            .collect()
+    }
+
+    /// returns how long the fallback sources can be searched for
+    fn fallback_timeout(&self) -> Option<std::time::Duration> {
+        self.fallback_timeout.map(std::time::Duration::from_millis)
+    }
+}
+
+/// parse a sort order such as `name`, `stars:desc` or `updated:asc`
+fn parse_sort(value: &str) -> Result<ItemSort, String> {
+    let (field, direction) = match value.split_once(':') {
+        Some((field, direction)) => (field, Some(direction)),
+        None => (value, None),
+    };
+
+    let field = match field {
+        "name" => SortField::Name,
+        "stars" => SortField::Stars,
+        "downloads" => SortField::Downloads,
+        "updated" => SortField::Updated,
+        _ => {
+            return Err(format!(
+                "unknown sort field {field:?}, expected one of name, stars, downloads, updated"
+            ))
+        }
+    };
+
+    let descending = match direction {
+        Some("asc") => false,
+        Some("desc") => true,
+        Some(direction) => {
+            return Err(format!(
+                "unknown sort direction {direction:?}, expected one of asc, desc"
+            ))
+        }
+        None => field != SortField::Name,
+    };
+
+    Ok(ItemSort { field, descending })
+}
+
+/// Maximum number of results returned by a search
+pub const RESULTS_LIMIT: usize = 5;
+
+/// Qualifiers accepted in a github search filter, mapped to their Github search API name
+const GH_QUALIFIERS: [(&str, &str); 3] = [
+    ("org:", "org:"),
+    ("lang:", "language:"),
+    ("topic:", "topic:"),
+];
+
+/// A github search filter, split between the free text and the qualifiers (e.g `org:rust-lang`)
+struct GhSearchFilter {
+    /// free text matched against the repository names
+    text: String,
+    /// qualifiers, already translated to the Github search syntax
+    qualifiers: Vec<String>,
+}
+
+impl GhSearchFilter {
+    /// parse the qualifiers out of the raw filter string
+    fn parse(filter: &str) -> Self {
+        let mut text = Vec::new();
+        let mut qualifiers = Vec::new();
+
+        for term in filter.split_whitespace() {
+            let qualifier = GH_QUALIFIERS.iter().find_map(|(prefix, gh_prefix)| {
+                term.strip_prefix(prefix)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| format!("{gh_prefix}{value}"))
+            });
+
+            match qualifier {
+                Some(qualifier) => qualifiers.push(qualifier),
+                None => text.push(term),
+            }
+        }
+
+        Self {
+            text: text.join(" "),
+            qualifiers,
+        }
+    }
+
+    /// returns the query string to send to the Github search API
+    fn to_gh_query(&self) -> String {
+        std::iter::once(self.text.as_str())
+            .chain(self.qualifiers.iter().map(String::as_str))
+            .filter(|term| !term.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// A client created in a background task as soon as the search starts, so that its setup (pool
+/// connection, token minting, TLS handshake) overlaps with the other stages of the search
+/// The task is cancelled with the runtime when the command finishes before the client is needed
+struct Prewarmed<T> {
+    create: fn() -> BoxFuture<'static, anyhow::Result<T>>,
+    task: tokio::sync::Mutex<Option<JoinHandle<anyhow::Result<T>>>>,
+    client: tokio::sync::OnceCell<T>,
+}
+
+impl<T: Send + 'static> Prewarmed<T> {
+    /// start creating the client in the background when enabled, or create it on first use
+    fn start(enabled: bool, create: fn() -> BoxFuture<'static, anyhow::Result<T>>) -> Self {
+        Self {
+            create,
+            task: tokio::sync::Mutex::new(enabled.then(|| tokio::spawn(create()))),
+            client: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    /// returns the client, created by the background task, or on the spot if the task failed
+    async fn get(&self) -> anyhow::Result<&T> {
+        self.client
+            .get_or_try_init(|| async {
+                if let Some(task) = self.task.lock().await.take() {
+                    match task.await? {
+                        Ok(client) => return Ok(client),
+                        Err(err) => log::warn!("failed to prewarm client: {err:?}"),
+                    }
+                }
+                (self.create)().await
+            })
+            .await
+    }
+}
+
+/// returns a new Github client, checking its authorization in the background, so that the
+/// connection is opened and the App token minted without delaying the first call
+async fn checked_gh_client() -> anyhow::Result<GHClient> {
+    let gh = GHClient::create().await?;
+    let check = gh.share();
+    tokio::spawn(async move {
+        if let Err(err) = check.check_auth().await {
+            log::warn!("failed to check the Github authorization: {err:?}");
+        }
+    });
+    Ok(gh)
+}
+
+/// A repository search, resolved against the config
+struct RepoQuery {
+    /// the query sent to GH
+    filter: GhSearchFilter,
+    /// the filters applied to the cached repositories
+    filters: RepoFilters,
+    /// the sources to search, in priority order
+    sources: Vec<Source>,
+}
+
+impl RepoQuery {
+    /// resolve the repository search of the given command arguments
+    fn new(
+        filter: &str,
+        since: Option<chrono::Duration>,
+        tag: Option<String>,
+        options: &SearchOptions,
+        config: &config::GhAlfredConfig,
+    ) -> Self {
+        let mut filter = GhSearchFilter::parse(filter);
+        let pushed_since = since.and_then(|since| chrono::Utc::now().checked_sub_signed(since));
+
+        if let Some(date) = pushed_since {
+            filter
+                .qualifiers
+                .push(format!("pushed:>={}", date.format("%Y-%m-%d")));
+        }
+
+        // qualified searches (org, language, topic) can't be resolved from the cache, since it only
+        // stores repository names, so they always go to GH
+        // tags only exist in the cache, so tagged searches never go to GH
+        let sources =
+            options.sources_supporting(&config.source_priority.repos, |source| match source {
+                Source::Cache => filter.qualifiers.is_empty(),
+                Source::Live => tag.is_none(),
+            });
+
+        // `fork:false` isn't a Github qualifier, the forks are filtered out of the live results
+        // with their fork flag instead, like the cached ones
+        Self {
+            filter,
+            filters: RepoFilters {
+                pushed_since: pushed_since
+                    .map(|date| date.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+                tag,
+                hide_forks: config.hide_forks,
+                owned_only: false,
+            },
+            sources,
+        }
+    }
+
+    /// returns the search restricted to the repositories of the authenticated user, its login
+    /// being resolved only when the live search needs it
+    async fn mine(
+        mut self,
+        config: &mut config::GhAlfredConfig,
+        gh: &Prewarmed<GHClient>,
+    ) -> anyhow::Result<Self> {
+        if self.sources.contains(&Source::Live) {
+            let login = viewer_login(config, gh).await?;
+            self.filter.qualifiers.push(format!("user:{login}"));
+        }
+        self.filters.owned_only = true;
+        Ok(self)
+    }
+}
+
+/// returns the login of the authenticated user, resolved once and cached in the config
+async fn viewer_login(
+    config: &mut config::GhAlfredConfig,
+    gh: &Prewarmed<GHClient>,
+) -> anyhow::Result<String> {
+    if let Some(login) = &config.viewer_login {
+        return Ok(login.clone());
+    }
+
+    // an App installation token isn't a user, so its login has to be configured
+    let login = gh
+        .get()
+        .await?
+        .viewer_login()
+        .await
+        .with_context(|| match config.github_app {
+            Some(_) => "a Github App has no user login, set viewer_login in the config for --mine",
+            None => "failed to resolve the login of the authenticated user",
+        })?;
+    config.update_viewer_login(login.clone());
+    Ok(login)
+}
+
+/// Execute the search github repository command
+pub async fn search_gh_repositories(
+    filter: String,
+    since: Option<chrono::Duration>,
+    tag: Option<String>,
+    mine: bool,
+    good_first_issues: bool,
+    repo_format: RepoFormat,
+    options: SearchOptions,
+) -> anyhow::Result<Vec<AlfredItem>> {
+    let start = std::time::Instant::now();
+    let mut config = config::GhAlfredConfig::load()?;
+    let query = RepoQuery::new(&filter, since, tag, &options, &config);
+
+    // only the clients of the searched sources are worth the setup, the Github client is shared
+    // by all the stages of the search
+    let db = &Prewarmed::start(
+        config.prewarm_clients && query.sources.contains(&Source::Cache),
+        || DBClient::create().boxed(),
+    );
+    let gh = &Prewarmed::start(
+        config.prewarm_clients
+            && (mine || good_first_issues || query.sources.contains(&Source::Live)),
+        || checked_gh_client().boxed(),
+    );
+
+    let RepoQuery {
+        filter,
+        filters,
+        sources,
+    } = if mine {
+        query.mine(&mut config, gh).await?
+    } else {
+        query
+    };
+
+    // the owners are capped after ranking, so all the candidates are kept until then, and more
+    // candidates are checked for good first issues than the results shown
+    let limit = config.adaptive_limit.limit(&filter.text, RESULTS_LIMIT);
+    let candidates = match good_first_issues {
+        true => GOOD_FIRST_ISSUES_CANDIDATES.max(limit),
+        false => limit,
+    };
+    let ranked_limit = match config.max_per_owner() {
+        Some(_) => usize::MAX,
+        None => candidates,
+    };
+
+    let (filter, filters) = (&filter, &filters);
+    let mut report = SearchReport::default();
+    let repositories = search_sources(
+        &sources,
+        config.search_strategy,
+        &options,
+        &RankingOptions {
+            query: &filter.text,
+            weights: &config.ranking_weights,
+            limit: ranked_limit,
+            selections: &selection_counts(db, &sources).await,
+        },
+        config.dedup_key,
+        &mut report,
+        |source| async move {
+            match source {
+                Source::Cache => Ok(db
+                    .get()
+                    .await?
+                    .search_repositories(&filter.text, filters)
+                    .await?
+                    .collect::<Vec<_>>()),
+                Source::Live => {
+                    let repositories = gh
+                        .get()
+                        .await?
+                        .search_repositories(&filter.to_gh_query())
+                        .await?;
+                    Ok(repositories
+                        .into_iter()
+                        .filter(|repo| !(filters.hide_forks && repo.fork))
+                        .collect())
+                }
+            }
+        },
+    )
+    .await?;
+
+    let repositories = match config.max_per_owner() {
+        Some(max_per_owner) => limit_per_owner(repositories, max_per_owner, candidates),
+        None => repositories,
+    };
+
+    let repositories = match good_first_issues {
+        true => with_good_first_issues(gh.get().await?, repositories, limit).await,
+        false => repositories,
+    };
+
+    // the favorites are pinned first, before fetching the details of the top items
+    // a favorite missing from the results can only be added when no filter could exclude it
+    let unfiltered = filters.pushed_since.is_none()
+        && filters.tag.is_none()
+        && !filters.hide_forks
+        && !filters.owned_only
+        && filter.qualifiers.is_empty()
+        && !good_first_issues;
+    let favorites = matching_favorites(&config.favorites, ItemSource::Github, &filter.text);
+    let (mut repositories, pinned) = pin_favorites(
+        repositories,
+        &favorites,
+        |item| &item.full_name,
+        |name| {
+            unfiltered.then(|| gh_client::GHApiRepoSearchItem {
+                full_name: name.to_string(),
+                ..Default::default()
+            })
+        },
+    );
+
+    // cached results only have a name, fetch the details and languages of the top ones
+    if config.enrich_limit() > 0 && sources.contains(&Source::Live) {
+        let gh = gh.get().await?;
+        enrich_top_items(&mut repositories, config.enrich_limit(), |item| {
+            let item = item.clone();
+            async move { gh.enrich_repository(item).await }
+        })
+        .await;
+    }
+
+    // fetching a README is expensive, so it's limited to the top result
+    if config.quicklook_readme && sources.contains(&Source::Live) {
+        if let Some(top) = repositories.first_mut() {
+            top.readme_path = gh
+                .get()
+                .await?
+                .readme_file(&top.full_name)
+                .await
+                .map_err(|err| log::warn!("failed to fetch README: {err:?}"))
+                .ok()
+                .flatten();
+        }
+    }
+
+    for repository in &mut repositories {
+        repository.local_path = local_clone(&config.clone_roots, &repository.full_name);
+    }
+
+    let results: Vec<AlfredItem> = repositories
+        .into_iter()
+        .enumerate()
+        .map(|(index, item): (_, gh_client::GHApiRepoSearchItem)| {
+            let item = AlfredItem::repository(item, repo_format, &config.gh_fields);
+            if index < pinned {
+                item.favorite()
+            } else {
+                item
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut results = apply_search_options(results, &options);
+    if options.explain {
+        report.pinned = pinned;
+        report.duration = start.elapsed();
+        report.explain(&mut results);
+    }
+    Ok(with_stale_warning(results, &sources, &options, &config))
+}
+
+/// Number of ranked repositories checked for good first issues, all in the same GraphQL query
+const GOOD_FIRST_ISSUES_CANDIDATES: usize = 20;
+
+/// Keep the first `limit` repositories having open good first issues, with their count
+/// The repositories whose count is unknown, e.g when the query failed, are kept without a count
+async fn with_good_first_issues(
+    gh: &GHClient,
+    repositories: Vec<gh_client::GHApiRepoSearchItem>,
+    limit: usize,
+) -> Vec<gh_client::GHApiRepoSearchItem> {
+    let full_names = repositories
+        .iter()
+        .map(|item| item.full_name.as_str())
+        .collect::<Vec<_>>();
+    let counts = gh
+        .good_first_issues(&full_names)
+        .await
+        .map_err(|err| log::warn!("failed to count the good first issues: {err:?}"))
+        .unwrap_or_else(|_| vec![None; repositories.len()]);
+
+    repositories
+        .into_iter()
+        .zip(counts)
+        .filter(|(_, count)| *count != Some(0))
+        .map(|(item, good_first_issues)| gh_client::GHApiRepoSearchItem {
+            good_first_issues,
+            ..item
+        })
+        .take(limit)
+        .collect()
+}
+
+/// returns the owner of a query ending with a slash, e.g `tokio-rs` for `tokio-rs/`
+pub fn owner_prefix(filter: &str) -> Option<&str> {
+    filter
+        .trim()
+        .strip_suffix('/')
+        .filter(|owner| !owner.is_empty() && !owner.contains(['/', ' ']))
+}
+
+/// Execute the search github repository command for an owner prefix, e.g `tokio-rs/`
+/// The cached repositories of the owner are listed, completing to their full name, falling back
+/// to a live search of the owner when none are cached
+pub async fn search_owner_repositories(
+    owner: String,
+    since: Option<chrono::Duration>,
+    tag: Option<String>,
+    mine: bool,
+    repo_format: RepoFormat,
+    options: SearchOptions,
+) -> anyhow::Result<Vec<AlfredItem>> {
+    let mut config = config::GhAlfredConfig::load()?;
+    let query = RepoQuery::new("", since, tag, &options, &config);
+    let gh = &Prewarmed::start(false, || checked_gh_client().boxed());
+    let RepoQuery {
+        filter,
+        filters,
+        sources,
+    } = if mine {
+        query.mine(&mut config, gh).await?
+    } else {
+        query
+    };
+
+    let mut repositories = Vec::new();
+    if sources.contains(&Source::Cache) {
+        let db = DBClient::create().await?;
+        repositories = db
+            .owner_repo_names(&owner, &filters, RESULTS_LIMIT)
+            .await?
+            .into_iter()
+            .map(|full_name| gh_client::GHApiRepoSearchItem {
+                full_name,
+                ..Default::default()
+            })
+            .collect();
+    }
+
+    // the results of other owners are dropped, e.g the ones of the user of --mine
+    if repositories.is_empty() && sources.contains(&Source::Live) {
+        let prefix = format!("{}/", owner.to_lowercase());
+        let search = async {
+            let query = format!("user:{owner} {}", filter.to_gh_query());
+            let repositories = gh.get().await?.search_repositories(query.trim()).await?;
+            Ok(repositories
+                .into_iter()
+                .filter(|repo| repo.full_name.to_lowercase().starts_with(&prefix))
+                .filter(|repo| !(filters.hide_forks && repo.fork))
+                .take(RESULTS_LIMIT)
+                .collect())
+        };
+        repositories = match options.wait_for_live {
+            true => search.await?,
+            false => pending_search::defer_if_slow(search).await?,
+        };
+    }
+
+    for repository in &mut repositories {
+        repository.local_path = local_clone(&config.clone_roots, &repository.full_name);
+    }
+
+    let results = repositories
+        .into_iter()
+        .map(|item| {
+            let full_name = item.full_name.clone();
+            AlfredItem {
+                autocomplete: Some(full_name),
+                ..AlfredItem::repository(item, repo_format, &config.gh_fields)
+            }
+        })
+        .collect();
+
+    let results = apply_search_options(results, &options);
+    Ok(with_stale_warning(results, &sources, &options, &config))
+}
+
+/// Execute the search topic command
+/// The Github topics matching the filter are listed, completing to `topic:<name> ` so that the
+/// completed query runs the repository search scoped to the topic
+pub async fn search_topics(
+    filter: String,
+    repo_format: RepoFormat,
+    options: SearchOptions,
+) -> anyhow::Result<Vec<AlfredItem>> {
+    if filter.trim_start().starts_with("topic:") {
+        let search = search_gh_repositories(filter, None, None, false, false, repo_format, options);
+        return search.await;
+    }
+
+    let filter = filter.trim();
+    if filter.is_empty() {
+        return Ok(vec![AlfredItem::info("Type a topic, e.g wasm")]);
+    }
+
+    let gh = GHClient::create().await?;
+    let results = gh
+        .search_topics(filter)
+        .await?
+        .into_iter()
+        .map(AlfredItem::topic)
+        .collect::<Vec<_>>();
+
+    if results.is_empty() {
+        return Ok(vec![AlfredItem::info(format!(
+            "No topic matching {filter}"
+        ))]);
+    }
+    Ok(results)
+}
+
+/// returns the local clone of the repository, as `<root>/<name>` or `<root>/<owner>/<name>` in
+/// the first clone root having it, a leading `~` being the home directory
+fn local_clone(roots: &[PathBuf], full_name: &str) -> Option<PathBuf> {
+    let (owner, name) = full_name.split_once('/')?;
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    roots
+        .iter()
+        .map(|root| match (root.strip_prefix("~"), &home) {
+            (Ok(relative), Some(home)) => home.join(relative),
+            _ => root.clone(),
+        })
+        .flat_map(|root| [root.join(name), root.join(owner).join(name)])
+        .find(|path| path.is_dir())
+}
+
+/// Keep the first `max_per_owner` ranked repositories of each owner, so that the next best
+/// repositories of the other owners move up, and the first `limit` of them
+fn limit_per_owner(
+    repositories: Vec<gh_client::GHApiRepoSearchItem>,
+    max_per_owner: usize,
+    limit: usize,
+) -> Vec<gh_client::GHApiRepoSearchItem> {
+    let mut counts = std::collections::HashMap::<String, usize>::new();
+    repositories
+        .into_iter()
+        .filter(|repo| {
+            let owner = repo.full_name.split('/').next().unwrap_or_default();
+            let count = counts.entry(owner.to_lowercase()).or_default();
+            *count += 1;
+            *count <= max_per_owner
+        })
+        .take(limit)
+        .collect()
+}
+
+/// Count the repositories matching the search, in the first source with matches
+pub async fn count_gh_repositories(
+    filter: String,
+    since: Option<chrono::Duration>,
+    tag: Option<String>,
+    mine: bool,
+    options: SearchOptions,
+) -> anyhow::Result<u64> {
+    let mut config = config::GhAlfredConfig::load()?;
+    let query = RepoQuery::new(&filter, since, tag, &options, &config);
+
+    // the Github client is created on first use, and shared with the login resolution
+    let gh = &Prewarmed::start(false, || GHClient::create().boxed());
+    let query = if mine {
+        query.mine(&mut config, gh).await?
+    } else {
+        query
+    };
+
+    for source in &query.sources {
+        let count = match source {
+            Source::Cache => {
+                let db = DBClient::create().await?;
+                let count = db.count_repos(&query.filter.text, &query.filters).await?;
+                u64::try_from(count)?
+            }
+            Source::Live => {
+                gh.get()
+                    .await?
+                    .count_repositories(&query.filter.to_gh_query())
+                    .await?
+            }
+        };
+        if count > 0 {
+            return Ok(count);
+        }
+    }
+
+    Ok(0)
+}
+
+/// Count the crates matching the search, in the first source with matches
+pub async fn count_crates(filter: String, options: SearchOptions) -> anyhow::Result<u64> {
+    let config = config::GhAlfredConfig::load()?;
+
+    for source in options.sources(&config.source_priority.crates) {
+        let count = match source {
+            Source::Cache => u64::try_from(DBClient::create().await?.count_crates(&filter).await?)?,
+            Source::Live => CrateClient::create().await?.count_crates(&filter).await?,
+        };
+        if count > 0 {
+            return Ok(count);
+        }
+    }
+
+    Ok(0)
+}
+
+/// Execute the search crate command
+pub async fn search_crate(
+    filter: String,
+    options: SearchOptions,
+) -> anyhow::Result<Vec<AlfredItem>> {
+    let start = std::time::Instant::now();
+    let config = config::GhAlfredConfig::load()?;
+    let sources = options.sources(&config.source_priority.crates);
+
+    let db = &Prewarmed::start(
+        config.prewarm_clients && sources.contains(&Source::Cache),
+        || DBClient::create().boxed(),
+    );
+
+    let filter = filter.as_str();
+    let mut report = SearchReport::default();
+    let circuit_open = &std::sync::Mutex::new(None);
+    let crates = search_sources(
+        &sources,
+        config.search_strategy,
+        &options,
+        &RankingOptions {
+            query: filter,
+            weights: &config.ranking_weights,
+            limit: config.adaptive_limit.limit(filter, RESULTS_LIMIT),
+            selections: &selection_counts(db, &sources).await,
+        },
+        config.dedup_key,
+        &mut report,
+        |source| async move {
+            match source {
+                Source::Cache => Ok(db
+                    .get()
+                    .await?
+                    .search_crates(filter)
+                    .await?
+                    .collect::<Vec<_>>()),
+                // the cached results are kept while the registry is unavailable
+                Source::Live => match CrateClient::create().await?.search_crate(filter).await {
+                    Err(err) if err.is::<crate_client::CircuitOpen>() => {
+                        log::warn!("skip the live search: {err}");
+                        *circuit_open.lock().unwrap_or_else(|err| err.into_inner()) =
+                            Some(err.to_string());
+                        Ok(Vec::new())
+                    }
+                    results => results,
+                },
+            }
+        },
+    )
+    .await?;
+
+    // the favorites are pinned first, before fetching the details of the top items
+    let favorites = matching_favorites(&config.favorites, ItemSource::Crate, filter);
+    let (mut crates, pinned) = pin_favorites(
+        crates,
+        &favorites,
+        |item| &item.name,
+        |name| {
+            Some(crate_client::CrateSearchItem {
+                name: name.to_string(),
+                ..Default::default()
+            })
+        },
+    );
+
+    // fetch the missing details and the downloads of the top results
+    if config.enrich_limit() > 0 && sources.contains(&Source::Live) {
+        let client = &CrateClient::create().await?;
+        enrich_top_items(&mut crates, config.enrich_limit(), |item| {
+            let item = item.clone();
+            async move { client.enrich_crate(item).await }
+        })
+        .await;
+
+        // the fetched descriptions are cached, so that the next searches can match them
+        if sources.contains(&Source::Cache) {
+            let described = crates
+                .iter()
+                .take(config.enrich_limit())
+                .filter(|item| item.description.is_some())
+                .cloned()
+                .collect::<Vec<_>>();
+            let save = async { db.get().await?.save_crates(&described).await };
+            if let Err(err) = save.await {
+                log::warn!("failed to cache the crate descriptions: {err:?}");
+            }
+        }
+    }
+
+    // near misses, e.g typos, get suggestions of close crate names
+    let mut suggestions = Vec::new();
+    if crates.is_empty() && config.crate_suggestions && sources.contains(&Source::Live) {
+        let client = CrateClient::create().await?;
+        suggestions = client
+            .suggest_crates(filter.trim())
+            .await
+            .map_err(|err| log::warn!("failed to suggest crates: {err:?}"))
+            .unwrap_or_default();
+    }
+
+    let mut others = crates.split_off(pinned);
+    let mut results: Vec<AlfredItem> = crates
+        .into_iter()
+        .map(|item| AlfredItem::from(item).favorite())
+        .collect();
+
+    // a valid crate name gets a direct link to its crates.io page first, after the favorites,
+    // unless it's already pinned as a favorite
+    let filter = filter.trim();
+    if crate_client::is_valid_crate_name(filter) {
+        let open_crate = AlfredItem::open_crate(filter);
+        others.retain(|item| item.name != filter);
+        if !results.iter().any(|item| item.uid == open_crate.uid) {
+            results.push(open_crate);
+        }
+    }
+
+    results.extend(others.into_iter().map(|item| item.into()));
+    results.extend(suggestions.iter().map(|name| AlfredItem::suggestion(name)));
+
+    let mut results = apply_search_options(results, &options);
+    if options.explain {
+        report.pinned = pinned;
+        report.duration = start.elapsed();
+        report.explain(&mut results);
+    }
+
+    // the first result of --open-first is opened directly, so it has to stay a result
+    let circuit_open = circuit_open.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(message) = circuit_open.as_ref().filter(|_| !options.open_first) {
+        results.insert(0, AlfredItem::info(message));
+    }
+    Ok(with_stale_warning(results, &sources, &options, &config))
+}
+
+/// Execute the top crates command, listing the `count` most downloaded crates
+pub async fn top_crates(count: usize) -> anyhow::Result<Vec<AlfredItem>> {
+    let client = CrateClient::create().await?;
+    let crates = client.top_crates(count).await?;
+    Ok(crates.into_iter().map(Into::into).collect())
+}
+
+/// Execute the search crate command for an exact crate name
+/// The crate is followed by its `with_versions` most recent versions, when set
+pub async fn find_crate(
+    name: String,
+    with_versions: Option<usize>,
+    options: SearchOptions,
+) -> anyhow::Result<Vec<AlfredItem>> {
+    let name = name.trim();
+    if !crate_client::is_valid_crate_name(name) {
+        return Ok(vec![invalid_crate_name(name)]);
+    }
+
+    let client = CrateClient::create().await?;
+    // the details already list the versions, so they are not fetched again
+    let (item, versions) = match client.find_crate_versions(name).await? {
+        Some(found) => found,
+        None => return Ok(vec![AlfredItem::info(format!("Crate {name} not found"))]),
+    };
+    let mut results = vec![client.enrich_crate(item).await?.into()];
+
+    if let Some(count) = with_versions {
+        results.extend(
+            versions
+                .iter()
+                .take(count)
+                .map(|version| AlfredItem::crate_version(name, version)),
+        );
+    }
+
+    Ok(apply_search_options(results, &options))
+}
+
+/// returns the info item of a name that can't be a crate, rejected before calling the registry
+fn invalid_crate_name(name: &str) -> AlfredItem {
+    AlfredItem::info(format!("{name:?} is not a valid crate name"))
+}
+
+/// Execute the crate dependencies command
+pub async fn crate_deps(name: String, version: Option<String>) -> anyhow::Result<Vec<AlfredItem>> {
+    let name = name.trim();
+    if !crate_client::is_valid_crate_name(name) {
+        return Ok(vec![invalid_crate_name(name)]);
+    }
+
+    let client = CrateClient::create().await?;
+    let version = match version {
+        Some(version) => version,
+        None => {
+            let item = match client.find_crate(name).await? {
+                Some(item) => item,
+                None => return Ok(vec![AlfredItem::info(format!("Crate {name} not found"))]),
+            };
+            match item.max_stable_version.or(item.max_version) {
+                Some(version) => version,
+                None => {
+                    return Ok(vec![AlfredItem::info(format!(
+                        "Crate {name} has no version"
+                    ))])
+                }
+            }
+        }
+    };
+
+    let dependencies = client.dependencies(name, &version).await?;
+    if dependencies.is_empty() {
+        return Ok(vec![AlfredItem::info(format!(
+            "{name} v{version} has no dependencies"
+        ))]);
+    }
+
+    Ok(dependencies.into_iter().map(AlfredItem::from).collect())
+}
+
+/// returns the number of times each item was selected, none when the database can't be opened,
+/// or isn't searched so that live searches don't open it only for the selections
+async fn selection_counts(
+    db: &Prewarmed<DBClient>,
+    sources: &[Source],
+) -> std::collections::HashMap<String, i64> {
+    if !sources.contains(&Source::Cache) {
+        return Default::default();
+    }
+
+    let counts = async { db.get().await?.selection_counts().await };
+    counts
+        .await
+        .map_err(|err| log::warn!("failed to read the selection counts: {err:?}"))
+        .unwrap_or_default()
+}
+
+/// Search the given sources, in order, according to the search strategy:
+/// - fallback: ranks the results of the first source with results
+/// - combined: merges the results of all sources (the first source wins on the duplicates of the
+///   given dedup key), and ranks them
+///
+/// The ranked results are truncated to `limit` items.
+/// The sources after the first one give up after the fallback timeout, keeping the results so far.
+/// Unless `wait_for_live` is set, a slow live search without results to show yet fails with
+/// `SearchDeferred`, to be finished in the background
+async fn search_sources<T, F, Fut>(
+    sources: &[Source],
+    strategy: SearchStrategy,
+    options: &SearchOptions,
+    ranking: &RankingOptions<'_>,
+    dedup_key: DedupKey,
+    report: &mut SearchReport,
+    search: F,
+) -> anyhow::Result<Vec<T>>
+where
+    T: Rankable,
+    F: Fn(Source) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<T>>>,
+{
+    report.strategy = strategy;
+    let mut items = Vec::new();
+    for (index, &source) in sources.iter().enumerate() {
+        log::debug!("search {source:?} source");
+        let start = std::time::Instant::now();
+
+        // the results are flagged when the fallback timeout expired
+        let search = async {
+            match options.fallback_timeout() {
+                Some(duration) if index > 0 => {
+                    match tokio::time::timeout(duration, search(source)).await {
+                        Ok(results) => Ok((results?, false)),
+                        Err(_) => {
+                            log::warn!("{source:?} source timed out after {duration:?}");
+                            Ok((Vec::new(), true))
+                        }
+                    }
+                }
+                _ => Ok((search(source).await?, false)),
+            }
+        };
+
+        // a slow live search without any result to show yet is finished in the background
+        let (results, timed_out) =
+            if source == Source::Live && items.is_empty() && !options.wait_for_live {
+                pending_search::defer_if_slow(search).await?
+            } else {
+                search.await?
+            };
+
+        report.sources.push(SourceReport {
+            source,
+            candidates: results.len(),
+            duration: start.elapsed(),
+            timed_out,
+        });
+
+        match strategy {
+            SearchStrategy::Fallback if !results.is_empty() => {
+                let results = ranking::rank(results, ranking);
+                report.ranked = results.len();
+                return Ok(results);
+            }
+            SearchStrategy::Fallback => {}
+            SearchStrategy::Combined => items = ranking::merge(items, results, dedup_key),
+        }
+    }
+
+    let items = ranking::rank(items, ranking);
+    report.ranked = items.len();
+    Ok(items)
+}
+
+/// How a search produced its results, reported with `--explain`
+#[derive(Default)]
+struct SearchReport {
+    strategy: SearchStrategy,
+    /// the searched sources, in order
+    sources: Vec<SourceReport>,
+    /// the number of ranked results kept
+    ranked: usize,
+    /// the number of pinned favorites
+    pinned: usize,
+    /// the duration of the whole search
+    duration: std::time::Duration,
+}
+
+/// The search of a single source
+struct SourceReport {
+    source: Source,
+    /// the number of results of the source, before ranking
+    candidates: usize,
+    duration: std::time::Duration,
+    /// whether the fallback timeout expired before the source returned
+    timed_out: bool,
+}
+
+impl SearchReport {
+    /// returns the lines describing the decision path of the search
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("strategy: {:?}", self.strategy).to_lowercase()];
+        for (index, report) in self.sources.iter().enumerate() {
+            let mut line = format!(
+                "{}: {} candidates in {}ms",
+                format!("{:?}", report.source).to_lowercase(),
+                report.candidates,
+                report.duration.as_millis()
+            );
+            if index > 0 && self.strategy == SearchStrategy::Fallback {
+                line.push_str(", fallback");
+            }
+            if report.timed_out {
+                line.push_str(", timed out");
+            }
+            lines.push(line);
+        }
+
+        let used = match self.strategy {
+            SearchStrategy::Combined => "merged".to_string(),
+            SearchStrategy::Fallback => self
+                .sources
+                .iter()
+                .find(|report| report.candidates > 0)
+                .map_or("none".to_string(), |report| {
+                    format!("{:?}", report.source).to_lowercase()
+                }),
+        };
+        lines.push(format!("used: {used}"));
+        lines.push(format!(
+            "ranked: {} results, {} pinned favorites, in {}ms",
+            self.ranked,
+            self.pinned,
+            self.duration.as_millis()
+        ));
+        lines
+    }
+
+    /// append the report to the results, as a trailing item in Alfred, or to stderr otherwise
+    fn explain(&self, results: &mut Vec<AlfredItem>) {
+        let lines = self.lines();
+        if !alfred::is_alfred() {
+            lines.iter().for_each(|line| eprintln!("explain: {line}"));
+            return;
+        }
+
+        let mut item = AlfredItem::info(format!("Explain: {}", lines[1..].join(" · ")));
+        item.subtitle = Some(lines.join(" · "));
+        results.push(item);
+    }
+}
+
+/// Replace the first `limit` items with their detailed version, fetched concurrently.
+/// Items whose details can't be fetched are left untouched
+async fn enrich_top_items<T, F, Fut>(items: &mut [T], limit: usize, fetch_details: F)
+where
+    F: Fn(&T) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let top = items.len().min(limit);
+    let details = futures::stream::iter(items[..top].iter().enumerate())
+        .map(|(index, item)| fetch_details(item).map(move |details| (index, details)))
+        .buffer_unordered(limit)
+        .collect::<Vec<_>>()
+        .await;
+
+    for (index, details) in details {
+        match details {
+            Ok(details) => items[index] = details,
+            Err(err) => log::warn!("failed to fetch item details: {err:?}"),
+        }
+    }
+}
+
+/// returns the names of the favorites of the given source matching the query, or all of them when
+/// the query is empty
+/// The favorites are identified by their uid, e.g `gh:owner/name` or `crate:name`
+fn matching_favorites<'a>(
+    favorites: &'a [String],
+    source: ItemSource,
+    query: &str,
+) -> Vec<&'a str> {
+    let query = query.trim().to_lowercase();
+    favorites
+        .iter()
+        .filter_map(|favorite| favorite.strip_prefix(source.prefix()))
+        .filter(|name| name.to_lowercase().contains(&query))
+        .collect()
+}
+
+/// Move the items of the given favorites first, in the favorites order, creating the ones missing
+/// from the results when `create` can. Returns the items, and the number of pinned favorites
+/// leading them
+fn pin_favorites<T>(
+    mut items: Vec<T>,
+    favorites: &[&str],
+    name: impl Fn(&T) -> &str,
+    create: impl Fn(&str) -> Option<T>,
+) -> (Vec<T>, usize) {
+    let mut pinned = favorites
+        .iter()
+        .filter_map(|favorite| {
+            match items
+                .iter()
+                .position(|item| name(item).eq_ignore_ascii_case(favorite))
+            {
+                Some(index) => Some(items.remove(index)),
+                None => create(favorite),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let count = pinned.len();
+    pinned.extend(items);
+    (pinned, count)
+}
+
+/// Post-process the search results according to the search options
+fn apply_search_options(mut results: Vec<AlfredItem>, options: &SearchOptions) -> Vec<AlfredItem> {
+    if let Some(sort) = options.sort {
+        sort.apply(&mut results);
+    }
+
+    if options.open_first {
+        if results.is_empty() {
+            return vec![AlfredItem::info("No results found")];
+        }
+        results.truncate(1);
+    }
+
+    results
+}
+
+/// Prepend a warning to the results searched in the cache, when it wasn't updated for
+/// `stale_warning_age_days`, e.g because the update daemon keeps failing
+fn with_stale_warning(
+    mut results: Vec<AlfredItem>,
+    sources: &[Source],
+    options: &SearchOptions,
+    config: &config::GhAlfredConfig,
+) -> Vec<AlfredItem> {
+    // the first result of --open-first is opened directly, so it has to stay a result
+    if options.open_first || !sources.contains(&Source::Cache) {
+        return results;
+    }
+
+    let warning = match config.stale_cache(&clock::SystemClock) {
+        Some(config::StaleCache::NeverSynced) => {
+            "Cache was never synced · run update-db to check the sync".to_string()
+        }
+        Some(config::StaleCache::DaysOld(days)) => {
+            format!("Cache is {days} days old · run update-db to check the sync")
+        }
+        None => return results,
+    };
+    results.insert(0, AlfredItem::info(warning));
+    results
+}
+
+/// Search the github repositories matching the query, with the configured sources and ranking
+/// A slow live search is waited for, instead of being deferred to the background
+pub async fn search_repositories(query: &str) -> anyhow::Result<Vec<AlfredItem>> {
+    let options = SearchOptions {
+        wait_for_live: true,
+        ..Default::default()
+    };
+    search_gh_repositories(
+        query.to_string(),
+        None,
+        None,
+        false,
+        false,
+        RepoFormat::default(),
+        options,
+    )
+    .await
+}
+
+/// Search the rust crates matching the query, with the configured sources and ranking
+/// A slow live search is waited for, instead of being deferred to the background
+pub async fn search_crates(query: &str) -> anyhow::Result<Vec<AlfredItem>> {
+    let options = SearchOptions {
+        wait_for_live: true,
+        ..Default::default()
+    };
+    search_crate(query.to_string(), options).await
+}
@@ -1,5 +1,10 @@
 //! Utilities to spawn a daemon
-use nix::unistd::{fork, setsid, ForkResult};
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+    unistd::{fork, setsid, ForkResult},
+};
+use std::{fs::File, os::unix::io::AsRawFd};
 
 /// Defines which branch of the fork we are on
 pub enum DaemonResult {
@@ -32,3 +37,22 @@ pub fn spawn_daemon() -> DaemonResult {
         }
     }
 }
+
+/// An exclusive lock held while updating the database, released when dropped
+pub struct UpdateLock {
+    _file: File,
+}
+
+impl UpdateLock {
+    /// try to acquire the update lock, returns None if another process already holds it
+    pub fn try_acquire() -> anyhow::Result<Option<Self>> {
+        let path = std::env::temp_dir().join(concat!(env!("CARGO_PKG_NAME"), ".lock"));
+        let file = File::create(path)?;
+
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(Errno::EWOULDBLOCK) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}